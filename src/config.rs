@@ -0,0 +1,56 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE: &str = ".codump.toml";
+
+/// defaults for a project, read from `.codump.toml`. every key is optional; a value
+/// here replaces the built-in default, and a flag on the command line replaces both
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub extensions: Option<List>,
+    pub exclude: Option<List>,
+    pub max_size: Option<usize>,
+    pub max_files: Option<usize>,
+    pub respect_gitignore: Option<bool>,
+}
+
+/// either `"rs,py"` like the flags take, or `["rs", "py"]`
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum List {
+    Joined(String),
+    Items(Vec<String>),
+}
+
+impl List {
+    /// comma-joined, the form the matching flag takes
+    pub fn joined(&self) -> String {
+        match self {
+            List::Joined(s) => s.clone(),
+            List::Items(items) => items.join(","),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&text).with_context(|| {
+            format!(
+                "invalid {} (its values override the built-in defaults, and command-line flags override it)",
+                path.display()
+            )
+        })
+    }
+
+    /// the nearest `.codump.toml` in `scan_root` or any directory above it
+    pub fn discover(scan_root: &Path) -> Option<PathBuf> {
+        let start = scan_root.canonicalize().ok()?;
+        // an archive isn't a directory, so start next to it
+        let start = if start.is_file() { start.parent()?.to_path_buf() } else { start };
+        start.ancestors().map(|dir| dir.join(CONFIG_FILE)).find(|path| path.is_file())
+    }
+}
@@ -0,0 +1,53 @@
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use log::debug;
+use std::collections::HashMap;
+
+use crate::source::FileSource;
+
+pub const DUMPIGNORE_FILE: &str = ".dumpignore";
+
+/// `.dumpignore` files found so far, for excludes that only concern the dump. they use
+/// gitignore syntax, and like `.gitignore` a deeper file overrides the ones above it, so
+/// a `!pattern` there re-includes what a parent's `.dumpignore` left out
+#[derive(Default)]
+pub struct DumpIgnore {
+    // keyed by the directory holding the file, relative to the scan root
+    matchers: HashMap<Utf8PathBuf, Gitignore>,
+}
+
+impl DumpIgnore {
+    /// picks up `dir/.dumpignore` if there is one. the walk visits directories before
+    /// their contents, so calling this on each directory it enters is enough
+    pub fn load_dir(&mut self, source: &dyn FileSource, dir: &Utf8Path) -> Result<()> {
+        let path = dir.join(DUMPIGNORE_FILE);
+        if source.file_size(&path).is_none() {
+            return Ok(());
+        }
+        let bytes = source.read(&path)?;
+        let mut builder = GitignoreBuilder::new(dir.as_std_path());
+        for line in String::from_utf8_lossy(&bytes).lines() {
+            builder.add_line(None, line).with_context(|| format!("invalid pattern in {}: {}", path, line))?;
+        }
+        let matcher = builder.build().with_context(|| format!("invalid {}", path))?;
+        debug!("using {} ({} patterns)", path, matcher.num_ignores() + matcher.num_whitelists());
+        self.matchers.insert(dir.to_owned(), matcher);
+        Ok(())
+    }
+
+    pub fn is_ignored(&self, rel_path: &Utf8Path, is_dir: bool) -> bool {
+        for dir in rel_path.ancestors().skip(1) {
+            let Some(matcher) = self.matchers.get(dir) else {
+                continue;
+            };
+            match matcher.matched(rel_path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,315 @@
+use camino::Utf8Path;
+use clap::ValueEnum;
+use humantime::format_rfc3339_seconds;
+use serde_json::{json, Map, Value};
+
+use crate::template::Template;
+use crate::{format_size, DirSummary, DumpFile};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// markdown headings with fenced code blocks
+    Markdown,
+    /// html comment delimiters for rag ingestion, no fences
+    Rag,
+    /// one json object with the tree and a `files` array
+    Json,
+}
+
+// json is a single document, so it's put together once everything is known (see
+// `json_document`). the per-section strings below only stand in for it in token estimates
+
+/// opens one root's section when several roots are dumped together
+pub fn root_header(format: Format, name: &str) -> String {
+    match format {
+        Format::Markdown => format!("# root: {}\n\n", name),
+        Format::Rag => format!("<!-- ROOT {} -->\n\n", name),
+        Format::Json => String::new(),
+    }
+}
+
+pub fn tree_section(format: Format, tree: &str) -> String {
+    match format {
+        Format::Markdown => format!("# project structure\n\n{}\n\n", tree),
+        Format::Rag => format!("<!-- BEGIN tree -->\n{}<!-- END tree -->\n\n", tree),
+        Format::Json => format!("\"tree\": {},", json!(tree)),
+    }
+}
+
+pub fn deps_section(format: Format, graph: &str) -> String {
+    match format {
+        Format::Markdown => format!("# dependency graph\n\n```mermaid\n{}```\n\n", graph),
+        Format::Rag => format!("<!-- BEGIN deps -->\n{}<!-- END deps -->\n\n", graph),
+        Format::Json => format!("\"dependency_graph\": {},", json!(graph)),
+    }
+}
+
+pub fn summary_line(format: Format, summary: &DirSummary) -> String {
+    let languages = summary.languages.iter().copied().collect::<Vec<_>>().join(", ");
+    match format {
+        Format::Markdown => format!(
+            "# directory: {}/ (summarized: {} {}, {}{}{})\n\n",
+            summary.path,
+            summary.files,
+            if summary.files == 1 { "file" } else { "files" },
+            format_size(summary.bytes),
+            if languages.is_empty() { "" } else { "; " },
+            languages
+        ),
+        Format::Rag => format!(
+            "<!-- SUMMARY {}/ files={} size={} languages={} -->\n\n",
+            summary.path,
+            summary.files,
+            summary.bytes,
+            languages.replace(", ", ",")
+        ),
+        Format::Json => format!("{},", summary_json(summary)),
+    }
+}
+
+/// renders a file, split into `(part N)` blocks of at most `chunk_lines` lines each
+/// when it is longer than that. a `template` replaces the markdown layout
+pub fn file_block(
+    format: Format,
+    file: &DumpFile,
+    chunk_lines: Option<usize>,
+    template: Option<&Template>,
+) -> String {
+    if format == Format::Json {
+        // chunking only helps a reader scrolling through text
+        return format!("{},", file_json(file));
+    }
+    if let Some(first) = &file.duplicate_of {
+        return duplicate_block(format, file, first, template);
+    }
+    let parts = match chunk_lines {
+        Some(limit) if file.content.split_inclusive('\n').count() > limit => {
+            let lines: Vec<&str> = file.content.split_inclusive('\n').collect();
+            lines.chunks(limit.max(1)).map(|chunk| chunk.concat()).collect()
+        }
+        _ => vec![file.content.clone()],
+    };
+    let render = |part: Option<usize>, content: &str| match template {
+        Some(template) => template.render(file, part, content),
+        None => part_block(format, file, part, content),
+    };
+    if parts.len() == 1 {
+        return render(None, &file.content);
+    }
+    parts.iter().enumerate().map(|(i, content)| render(Some(i + 1), content)).collect()
+}
+
+fn part_block(format: Format, file: &DumpFile, part: Option<usize>, content: &str) -> String {
+    match format {
+        Format::Markdown => {
+            let fence = fence_for(content);
+            format!(
+                "# file: {}{}{}{}{}{}\n\n{}{}{}\n{}\n{}\n\n",
+                file.path,
+                part.map(|n| format!(" (part {})", n)).unwrap_or_default(),
+                file.mode.map(mode_note).unwrap_or_default(),
+                file.modified.map(|t| format!(" (modified {})", format_rfc3339_seconds(t))).unwrap_or_default(),
+                file.author.as_ref().map(|a| format!(" (last commit by {})", a)).unwrap_or_default(),
+                file.generated.map(|g| format!(" (likely {})", g.label())).unwrap_or_default(),
+                file.decoded_from.map(|e| format!("<!-- decoded as {} -->\n", e)).unwrap_or_default(),
+                fence,
+                file.language,
+                content,
+                fence
+            )
+        }
+        Format::Rag => format!(
+            "<!-- BEGIN {} lang={}{}{}{}{}{}{} -->\n{}\n<!-- END {} -->\n\n",
+            file.path,
+            file.language,
+            part.map(|n| format!(" part={}", n)).unwrap_or_default(),
+            file.mode.map(|m| format!(" mode={:04o}", m)).unwrap_or_default(),
+            file.decoded_from.map(|e| format!(" encoding={}", e)).unwrap_or_default(),
+            file.modified.map(|t| format!(" modified={}", format_rfc3339_seconds(t))).unwrap_or_default(),
+            file.author.as_ref().map(|a| format!(" author=\"{}\"", a)).unwrap_or_default(),
+            file.generated.map(|g| format!(" likely={}", g.label())).unwrap_or_default(),
+            content,
+            file.path
+        ),
+        Format::Json => unreachable!("json files are never split"),
+    }
+}
+
+/// stands in for a file with the same content as `first`, which was dumped earlier
+fn duplicate_block(format: Format, file: &DumpFile, first: &Utf8Path, template: Option<&Template>) -> String {
+    let note = format!("[duplicate of {}]", first);
+    match (template, format) {
+        (Some(template), _) => template.render(file, None, &note),
+        (None, Format::Markdown) => format!("# file: {}\n\n{}\n\n", file.path, note),
+        (None, Format::Rag) => format!("<!-- DUPLICATE {} of={} -->\n\n", file.path, first),
+        (None, Format::Json) => unreachable!("json files carry duplicate_of instead"),
+    }
+}
+
+/// a backtick fence one longer than the longest run of backticks in `content` (and at
+/// least three), so nothing inside can close the block early
+pub fn fence_for(content: &str) -> String {
+    let longest = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat((longest + 1).max(3))
+}
+
+/// header suffix like ` (mode 0755, executable)`
+fn mode_note(mode: u32) -> String {
+    let executable = if mode & 0o111 != 0 { ", executable" } else { "" };
+    format!(" (mode {:04o}{})", mode, executable)
+}
+
+/// opens each file written by --split
+pub fn part_header(format: Format, part: usize, total: usize) -> String {
+    match format {
+        Format::Markdown | Format::Json => format!("# part {} of {}\n\n", part, total),
+        Format::Rag => format!("<!-- PART {} of {} -->\n", part, total),
+    }
+}
+
+/// ends a dump that --max-output cut short
+pub fn output_cut_marker(format: Format, files: usize, left_out: usize) -> String {
+    match format {
+        Format::Markdown | Format::Json => format!(
+            "... [output truncated at {} files due to --max-output; {} more files left out] ...\n",
+            files, left_out
+        ),
+        Format::Rag => format!("<!-- TRUNCATED at {} files by --max-output, {} more left out -->\n", files, left_out),
+    }
+}
+
+pub fn checksum_line(format: Format, checksum: u64) -> String {
+    match format {
+        Format::Markdown => format!("# tree checksum: {:016x}\n", checksum),
+        Format::Rag => format!("<!-- TREE-CHECKSUM {:016x} -->\n", checksum),
+        Format::Json => format!("\"tree_checksum\": \"{:016x}\",", checksum),
+    }
+}
+
+fn summary_json(summary: &DirSummary) -> Value {
+    json!({
+        "path": summary.path.as_str(),
+        "files": summary.files,
+        "size_bytes": summary.bytes,
+        "languages": summary.languages,
+    })
+}
+
+fn file_json(file: &DumpFile) -> Value {
+    let mut object = json!({
+        "path": file.path.as_str(),
+        "language": file.language,
+        "size_bytes": file.size_bytes,
+        "content": file.content,
+    });
+    if let Some(mode) = file.mode {
+        object["mode"] = json!(format!("{:04o}", mode));
+    }
+    if let Some(encoding) = file.decoded_from {
+        object["decoded_from"] = json!(encoding);
+    }
+    if let Some(modified) = file.modified {
+        object["modified"] = json!(format_rfc3339_seconds(modified).to_string());
+    }
+    if let Some(author) = &file.author {
+        object["author"] = json!(author);
+    }
+    if let Some(generated) = file.generated {
+        object["likely"] = json!(generated.label());
+    }
+    if let Some(first) = &file.duplicate_of {
+        object["duplicate_of"] = json!(first.as_str());
+    }
+    object
+}
+
+/// the whole `--format json` output. optional sections are left out rather than null
+pub fn json_document(
+    tree: &str,
+    summaries: &[DirSummary],
+    graph: Option<&str>,
+    checksum: Option<u64>,
+    files: &[DumpFile],
+    pretty: bool,
+) -> String {
+    let mut document = Map::new();
+    document.insert("tree".into(), json!(tree));
+    if !summaries.is_empty() {
+        document.insert("summaries".into(), summaries.iter().map(summary_json).collect());
+    }
+    if let Some(graph) = graph {
+        document.insert("dependency_graph".into(), json!(graph));
+    }
+    if let Some(checksum) = checksum {
+        document.insert("tree_checksum".into(), json!(format!("{:016x}", checksum)));
+    }
+    document.insert("files".into(), files.iter().map(file_json).collect());
+
+    let document = Value::Object(document);
+    let text = if pretty { serde_json::to_string_pretty(&document) } else { serde_json::to_string(&document) };
+    text.expect("a json value always serializes") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn summary_line_counts_files_in_words() {
+        let mut summary = DirSummary {
+            path: "third_party".into(),
+            files: 1,
+            bytes: 2048,
+            languages: BTreeSet::from(["rust"]),
+        };
+        assert_eq!(
+            summary_line(Format::Markdown, &summary),
+            "# directory: third_party/ (summarized: 1 file, 2.0 KiB; rust)\n\n"
+        );
+        summary.files = 2;
+        assert!(summary_line(Format::Markdown, &summary).contains("(summarized: 2 files, "));
+    }
+
+    fn rust_file(lines: usize) -> DumpFile {
+        let content: String = (1..=lines).map(|n| format!("let x{} = {};\n", n, n)).collect();
+        DumpFile {
+            path: "src/big.rs".into(),
+            language: "rust".to_string(),
+            size_bytes: content.len() as u64,
+            mode: None,
+            decoded_from: None,
+            modified: None,
+            author: None,
+            duplicate_of: None,
+            generated: None,
+            content,
+        }
+    }
+
+    #[test]
+    fn a_file_one_line_over_the_chunk_size_gets_a_second_part() {
+        let block = file_block(Format::Markdown, &rust_file(4), Some(3), None);
+        assert_eq!(
+            block,
+            "# file: src/big.rs (part 1)\n\n```rust\nlet x1 = 1;\nlet x2 = 2;\nlet x3 = 3;\n\n```\n\n\
+             # file: src/big.rs (part 2)\n\n```rust\nlet x4 = 4;\n\n```\n\n"
+        );
+        // at the limit it stays whole and unnumbered
+        let block = file_block(Format::Markdown, &rust_file(3), Some(3), None);
+        assert!(block.starts_with("# file: src/big.rs\n\n"), "{}", block);
+        assert!(!block.contains("(part "));
+    }
+
+    #[test]
+    fn chunked_rag_parts_keep_every_line_once_and_in_order() {
+        let file = rust_file(7);
+        let block = file_block(Format::Rag, &file, Some(3), None);
+        let parts: Vec<&str> = block.matches(" part=").collect();
+        assert_eq!(parts.len(), 3);
+        assert!(block.contains("<!-- BEGIN src/big.rs lang=rust part=1 -->"));
+        assert!(block.contains("<!-- BEGIN src/big.rs lang=rust part=3 -->\nlet x7 = 7;\n\n"));
+        let lines: Vec<&str> = block.lines().filter(|line| line.starts_with("let ")).collect();
+        assert_eq!(lines, file.content.lines().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,86 @@
+use regex::Regex;
+
+use crate::{line_number_width, numbered_line};
+
+/// marks lines left out between two context windows
+pub const GAP: &str = "...";
+
+/// keeps only the lines matching `pattern` plus `context` lines either side, or returns
+/// `None` if nothing matches. windows that overlap or touch are merged into one, like
+/// `grep -C`, so no line is ever printed twice. `line_numbers` numbers the kept lines
+/// as they are numbered in the whole file
+pub fn excerpt(content: &str, pattern: &Regex, context: usize, line_numbers: bool) -> Option<String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let mut windows: Vec<(usize, usize)> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if !pattern.is_match(line.trim_end_matches(['\n', '\r'])) {
+            continue;
+        }
+        let (start, end) = (i.saturating_sub(context), (i + context).min(lines.len() - 1));
+        match windows.last_mut() {
+            Some(last) if start <= last.1 + 1 => last.1 = end,
+            _ => windows.push((start, end)),
+        }
+    }
+    if windows.is_empty() {
+        return None;
+    }
+
+    let width = line_number_width(lines.len());
+    let mut excerpt = String::new();
+    for (n, &(start, end)) in windows.iter().enumerate() {
+        if n > 0 {
+            excerpt.push_str(GAP);
+            excerpt.push('\n');
+        }
+        for (i, line) in lines.iter().enumerate().take(end + 1).skip(start) {
+            if line_numbers {
+                excerpt.push_str(&numbered_line(width, i + 1, line));
+            } else {
+                excerpt.push_str(line);
+            }
+        }
+    }
+    Some(excerpt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // line n reads `n`, with a match on lines 2, 4 and 10
+    fn numbered(count: usize) -> String {
+        (1..=count)
+            .map(|n| if [2, 4, 10].contains(&n) { format!("{} match\n", n) } else { format!("{}\n", n) })
+            .collect()
+    }
+
+    #[test]
+    fn overlapping_windows_merge_and_separate_ones_get_a_gap() {
+        let pattern = Regex::new("match").unwrap();
+        let excerpt = excerpt(&numbered(12), &pattern, 1, false).unwrap();
+        // 1-3 and 3-5 overlap, 9-11 stands apart
+        assert_eq!(excerpt, "1\n2 match\n3\n4 match\n5\n...\n9\n10 match\n11\n");
+    }
+
+    #[test]
+    fn windows_that_only_touch_are_merged_too() {
+        // with two lines of context around lines 2 and 7, 1..4 ends right where 5..9 starts
+        let pattern = Regex::new("^(2 match|7)$").unwrap();
+        let excerpt = excerpt(&numbered(12), &pattern, 2, false).unwrap();
+        assert_eq!(excerpt, "1\n2 match\n3\n4 match\n5\n6\n7\n8\n9\n");
+    }
+
+    #[test]
+    fn context_stops_at_the_ends_of_the_file() {
+        let pattern = Regex::new("^(1|12)$").unwrap();
+        let excerpt = excerpt(&numbered(12), &pattern, 3, true).unwrap();
+        assert_eq!(excerpt, " 1 | 1\n 2 | 2 match\n 3 | 3\n 4 | 4 match\n...\n 9 | 9\n10 | 10 match\n11 | 11\n12 | 12\n");
+    }
+
+    #[test]
+    fn no_match_means_no_excerpt() {
+        assert_eq!(excerpt(&numbered(5), &Regex::new("absent").unwrap(), 2, false), None);
+        assert_eq!(excerpt("", &Regex::new("").unwrap(), 2, false), None);
+    }
+}
@@ -0,0 +1,166 @@
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use regex::Regex;
+use std::collections::{BTreeSet, HashSet};
+
+use crate::file_extension;
+
+/// more edges than this and the rendered graph stops being readable
+pub const MAX_GRAPH_EDGES: usize = 100;
+
+lazy_static::lazy_static! {
+    static ref RUST_MOD_RE: Regex =
+        Regex::new(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?mod\s+(\w+)\s*;").unwrap();
+    static ref RUST_USE_RE: Regex = Regex::new(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?use\s+crate::(\w+)").unwrap();
+    static ref PY_FROM_RE: Regex = Regex::new(r"(?m)^\s*from\s+(\.*)([\w.]*)\s+import\b").unwrap();
+    static ref PY_IMPORT_RE: Regex = Regex::new(r"(?m)^\s*import\s+([\w.]+)").unwrap();
+    static ref JS_IMPORT_RE: Regex =
+        Regex::new(r#"(?:\bfrom|\bimport|\brequire\()\s*['"](\.[^'"]+)['"]"#).unwrap();
+    static ref C_INCLUDE_RE: Regex = Regex::new(r#"(?m)^\s*#\s*include\s*"([^"]+)""#).unwrap();
+}
+
+const JS_EXTENSIONS: &[&str] = &["ts", "tsx", "js", "jsx", "mjs", "cjs"];
+
+/// best-effort list of the files in `files` that `path` imports. only relative and
+/// crate-local imports can point at another dumped file, so everything else is ignored
+pub fn resolve_imports(
+    path: &Utf8Path,
+    content: &str,
+    files: &HashSet<&Utf8Path>,
+) -> Vec<Utf8PathBuf> {
+    let dir = path.parent().unwrap_or(Utf8Path::new(""));
+    let ext = file_extension(path);
+    let mut candidates: Vec<Vec<Utf8PathBuf>> = Vec::new();
+
+    match ext.as_str() {
+        "rs" => {
+            // `mod foo;` in main.rs, lib.rs or mod.rs is a sibling; anywhere else it's a child
+            let base = match path.file_name() {
+                Some("main.rs" | "lib.rs" | "mod.rs") => dir.to_owned(),
+                _ => dir.join(path.file_stem().unwrap_or("")),
+            };
+            for caps in RUST_MOD_RE.captures_iter(content) {
+                candidates.push(rust_module(&base, &caps[1]));
+            }
+            let crate_root = dir.ancestors().find(|d| {
+                files.contains(d.join("main.rs").as_path()) || files.contains(d.join("lib.rs").as_path())
+            });
+            if let Some(crate_root) = crate_root {
+                for caps in RUST_USE_RE.captures_iter(content) {
+                    candidates.push(rust_module(crate_root, &caps[1]));
+                }
+            }
+        }
+        "py" => {
+            for caps in PY_FROM_RE.captures_iter(content) {
+                let dots = caps[1].len();
+                let module = caps[2].replace('.', "/");
+                if dots == 0 {
+                    candidates.push(python_absolute(dir, &module));
+                } else {
+                    let mut base = dir.to_owned();
+                    for _ in 1..dots {
+                        base = base.parent().map(Utf8Path::to_owned).unwrap_or_default();
+                    }
+                    candidates.push(python_module(&base, &module));
+                }
+            }
+            for caps in PY_IMPORT_RE.captures_iter(content) {
+                candidates.push(python_absolute(dir, &caps[1].replace('.', "/")));
+            }
+        }
+        e if JS_EXTENSIONS.contains(&e) => {
+            for caps in JS_IMPORT_RE.captures_iter(content) {
+                let Some(target) = join_normalized(dir, &caps[1]) else {
+                    continue;
+                };
+                let mut paths = vec![target.clone()];
+                for ext in JS_EXTENSIONS {
+                    paths.push(Utf8PathBuf::from(format!("{}.{}", target, ext)));
+                    paths.push(target.join(format!("index.{}", ext)));
+                }
+                candidates.push(paths);
+            }
+        }
+        "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" | "hxx" => {
+            for caps in C_INCLUDE_RE.captures_iter(content) {
+                // quoted includes look next to the including file first
+                candidates.push(
+                    [join_normalized(dir, &caps[1]), join_normalized(Utf8Path::new(""), &caps[1])]
+                        .into_iter()
+                        .flatten()
+                        .collect(),
+                );
+            }
+        }
+        _ => {}
+    }
+
+    let mut resolved = BTreeSet::new();
+    for paths in candidates {
+        if let Some(found) = paths.into_iter().find(|p| p != path && files.contains(p.as_path())) {
+            resolved.insert(found);
+        }
+    }
+    resolved.into_iter().collect()
+}
+
+fn rust_module(base: &Utf8Path, name: &str) -> Vec<Utf8PathBuf> {
+    vec![base.join(format!("{}.rs", name)), base.join(name).join("mod.rs")]
+}
+
+fn python_module(base: &Utf8Path, module: &str) -> Vec<Utf8PathBuf> {
+    if module.is_empty() {
+        return vec![base.join("__init__.py")];
+    }
+    vec![base.join(format!("{}.py", module)), base.join(module).join("__init__.py")]
+}
+
+// absolute imports are resolved against every directory from the importing file up to
+// the scan root, since there's no telling where the package root is
+fn python_absolute(dir: &Utf8Path, module: &str) -> Vec<Utf8PathBuf> {
+    dir.ancestors().flat_map(|base| python_module(base, module)).collect()
+}
+
+/// joins a relative import onto `dir`, or `None` if it climbs above the scan root
+fn join_normalized(dir: &Utf8Path, relative: &str) -> Option<Utf8PathBuf> {
+    let mut path = Utf8PathBuf::new();
+    for component in dir.join(relative).components() {
+        match component {
+            Utf8Component::Normal(part) => path.push(part),
+            Utf8Component::ParentDir => path.pop().then_some(())?,
+            _ => {}
+        }
+    }
+    Some(path)
+}
+
+/// renders `from --> to` edges as a mermaid flowchart, keeping only the first
+/// `MAX_GRAPH_EDGES` and noting how many were cut
+pub fn mermaid_graph(edges: &[(Utf8PathBuf, Utf8PathBuf)]) -> String {
+    let mut nodes: Vec<&Utf8Path> = Vec::new();
+    let mut graph = String::from("graph LR\n");
+    let mut lines = Vec::new();
+    for (from, to) in edges.iter().take(MAX_GRAPH_EDGES) {
+        let ids: Vec<usize> = [from, to]
+            .into_iter()
+            .map(|path| match nodes.iter().position(|n| *n == path) {
+                Some(i) => i,
+                None => {
+                    nodes.push(path);
+                    nodes.len() - 1
+                }
+            })
+            .collect();
+        lines.push(format!("    n{} --> n{}\n", ids[0], ids[1]));
+    }
+    for (i, node) in nodes.iter().enumerate() {
+        graph.push_str(&format!("    n{}[\"{}\"]\n", i, node.as_str().replace('"', "#quot;")));
+    }
+    for line in lines {
+        graph.push_str(&line);
+    }
+    if edges.len() > MAX_GRAPH_EDGES {
+        graph.push_str(&format!("    %% {} more edges omitted\n", edges.len() - MAX_GRAPH_EDGES));
+    }
+    graph
+}
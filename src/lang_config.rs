@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use globset::{Glob, GlobMatcher};
+use std::collections::{HashMap, HashSet};
+
+use crate::source::FileSource;
+use crate::{file_extension, normalize_extension};
+
+const LANG_CONFIG_FILE: &str = ".codump-lang";
+
+#[derive(Debug)]
+enum Key {
+    Extension(String),
+    Glob(GlobMatcher),
+}
+
+#[derive(Debug)]
+struct Rule {
+    key: Key,
+    language: String,
+}
+
+/// per-directory language overrides read from `.codump-lang` files. each line is
+/// `pattern = language`, where the pattern is either a bare extension (`tmpl`) or a
+/// glob relative to the directory holding the file (`templates/*.tmpl`)
+#[derive(Debug, Default)]
+pub struct LangOverrides {
+    // keyed by directory relative to the scan root
+    rules: HashMap<Utf8PathBuf, Vec<Rule>>,
+}
+
+impl LangOverrides {
+    /// reads the config files in every directory that could apply to `files`
+    pub fn load(source: &dyn FileSource, files: &[Utf8PathBuf]) -> Result<Self> {
+        let mut overrides = LangOverrides::default();
+        let mut seen = HashSet::new();
+        for file in files {
+            for dir in file.ancestors().skip(1) {
+                if !seen.insert(dir.to_owned()) {
+                    continue;
+                }
+                let config = dir.join(LANG_CONFIG_FILE);
+                if source.file_size(&config).is_none() {
+                    continue;
+                }
+                let text = String::from_utf8(source.read(&config)?)
+                    .with_context(|| format!("failed to read {}", config))?;
+                let rules = parse(&text).with_context(|| format!("invalid {}", config))?;
+                overrides.rules.insert(dir.to_owned(), rules);
+            }
+        }
+
+        Ok(overrides)
+    }
+
+    /// the nearest directory with a matching rule wins; within a file the first match wins
+    pub fn language_for(&self, rel_path: &Utf8Path) -> Option<&str> {
+        let ext = file_extension(rel_path);
+        rel_path.ancestors().skip(1).find_map(|dir| {
+            let rules = self.rules.get(dir)?;
+            let local = rel_path.strip_prefix(dir).unwrap_or(rel_path);
+            rules
+                .iter()
+                .find(|rule| match &rule.key {
+                    Key::Extension(e) => *e == ext,
+                    Key::Glob(glob) => glob.is_match(local),
+                })
+                .map(|rule| rule.language.as_str())
+        })
+    }
+}
+
+fn parse(text: &str) -> Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (pattern, language) = line
+            .split_once('=')
+            .with_context(|| format!("line {}: expected `pattern = language`", number + 1))?;
+        let (pattern, language) = (pattern.trim(), language.trim());
+
+        let key = if pattern.contains(['*', '?', '[', '{', '/']) {
+            Key::Glob(
+                Glob::new(pattern)
+                    .with_context(|| format!("line {}: invalid glob {}", number + 1, pattern))?
+                    .compile_matcher(),
+            )
+        } else {
+            Key::Extension(normalize_extension(pattern))
+        };
+        rules.push(Rule { key, language: language.to_string() });
+    }
+
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source;
+    use crate::test_support::TempDir;
+
+    fn overrides_for(root: &TempDir, files: &[&str]) -> LangOverrides {
+        let source = source::open(root.path().as_str(), false, false).unwrap();
+        let files: Vec<Utf8PathBuf> = files.iter().map(Utf8PathBuf::from).collect();
+        LangOverrides::load(source.as_ref(), &files).unwrap()
+    }
+
+    #[test]
+    fn nearest_lang_config_wins() {
+        let root = TempDir::new();
+        root.write(".codump-lang", "tmpl = jinja\ninc = php\n");
+        root.write("web/.codump-lang", "# plain html here\ntmpl = html\n");
+        let overrides = overrides_for(&root, &["a.tmpl", "web/page.tmpl", "web/deep/x.tmpl", "web/lib.inc"]);
+        assert_eq!(overrides.language_for("a.tmpl".into()), Some("jinja"));
+        assert_eq!(overrides.language_for("web/page.tmpl".into()), Some("html"));
+        assert_eq!(overrides.language_for("web/deep/x.tmpl".into()), Some("html"));
+        // a nearer file without a matching rule falls through to the next one up
+        assert_eq!(overrides.language_for("web/lib.inc".into()), Some("php"));
+        assert_eq!(overrides.language_for("web/main.rs".into()), None);
+    }
+
+    #[test]
+    fn lang_config_globs_are_relative_to_their_directory() {
+        let root = TempDir::new();
+        root.write("site/.codump-lang", "templates/*.txt = handlebars\n*.txt = markdown\n");
+        let overrides = overrides_for(&root, &["site/templates/a.txt", "site/notes.txt"]);
+        assert_eq!(overrides.language_for("site/templates/a.txt".into()), Some("handlebars"));
+        assert_eq!(overrides.language_for("site/notes.txt".into()), Some("markdown"));
+        assert_eq!(overrides.language_for("notes.txt".into()), None);
+    }
+
+    #[test]
+    fn lang_config_lines_need_an_equals_sign() {
+        let error = parse("tmpl = jinja\ntmpl jinja\n").unwrap_err();
+        assert!(error.to_string().contains("line 2"), "{}", error);
+    }
+}
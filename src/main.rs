@@ -1,14 +1,52 @@
 use anyhow::{Context, Result};
 use arboard::Clipboard;
 use camino::{Utf8Path, Utf8PathBuf};
-use clap::Parser;
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
 use phf::phf_map;
 use rayon::prelude::*;
-use std::{fs, thread, time::Duration};
-use std::io::Read;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::{cmp::Ordering, collections::{BTreeSet, HashMap, HashSet}, fs, thread, time::{Duration, SystemTime}};
+use std::path::{Path, PathBuf};
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
 use log::{warn, debug};
 use regex::Regex;
-use walkdir::WalkDir;
+use unicode_normalization::UnicodeNormalization;
+
+mod config;
+mod dumpignore;
+mod format;
+mod grep;
+mod imports;
+mod lang_config;
+mod redact;
+mod select;
+mod self_check;
+mod skips;
+mod source;
+mod strip;
+mod template;
+mod tokens;
+mod vcs;
+mod watch;
+
+#[cfg(test)]
+mod test_support;
+
+use config::Config;
+use dumpignore::DumpIgnore;
+use format::Format;
+use lang_config::LangOverrides;
+use redact::Redactor;
+use select::{Candidate, Selection};
+use skips::{SkipReason, SkipReport};
+use source::{Entry, FileSource, Visit};
+use template::{Preset, Template};
+use tokens::Pack;
+use vcs::{ChangedFiles, VcsIgnore};
 
 static LANG_MAP: phf::Map<&'static str, &'static str> = phf_map! {
     "rs" => "rust",
@@ -71,6 +109,32 @@ static LANG_MAP: phf::Map<&'static str, &'static str> = phf_map! {
     "txt" => "text",
     "csv" => "csv",
     "org" => "org",
+    "zig" => "zig",
+    "dart" => "dart",
+    "vue" => "vue",
+    "svelte" => "svelte",
+    "tf" => "hcl",
+    "tfvars" => "hcl",
+    "proto" => "protobuf",
+    "nix" => "nix",
+};
+
+// files known by their whole (lowercased) name, which wins over the extension
+static FILENAME_MAP: phf::Map<&'static str, &'static str> = phf_map! {
+    "dockerfile" => "dockerfile",
+    "containerfile" => "dockerfile",
+    "makefile" => "makefile",
+    "gnumakefile" => "makefile",
+    "cmakelists.txt" => "cmake",
+    "gemfile" => "ruby",
+    "rakefile" => "ruby",
+    "vagrantfile" => "ruby",
+    "jenkinsfile" => "groovy",
+    ".gitignore" => "gitignore",
+    ".dockerignore" => "gitignore",
+    ".bashrc" => "bash",
+    ".zshrc" => "bash",
+    ".profile" => "bash",
 };
 
 const DEFAULT_EXTENSIONS_STR: &str = concat!(
@@ -79,9 +143,15 @@ const DEFAULT_EXTENSIONS_STR: &str = concat!(
     "ex,exs,elm,hs,erl,fs,sh,bash,zsh,fish,ps1,json,toml,",
     "yaml,yml,xml,ini,conf,properties,sql,graphql,gql,prisma,",
     "md,markdown,rst,txt,csv,org,html,css,scss,sass,less,tex,",
-    "rmd,bat"
+    "rmd,bat,zig,dart,vue,svelte,tf,tfvars,proto,nix"
 );
 
+/// kept free under --max-output for the line saying the dump was cut short
+const OUTPUT_CUT_RESERVE: u64 = 128;
+
+/// names longer than this don't widen the size column of their directory in the tree
+const MAX_ALIGNED_PATH: usize = 60;
+
 const DEFAULT_EXCLUDES_STR: &str = concat!(
     ".git,node_modules,target,dist,build,venv,.venv,__pycache__,",
     ".idea,.vscode,bin,obj,.mypy_cache,debug,.fingerprint,.cache,",
@@ -99,23 +169,47 @@ const DEFAULT_EXCLUDES_STR: &str = concat!(
     version
 )]
 struct Cli {
-    #[arg(default_value = ".", help = "directory to scan")]
-    directory: String,
+    #[arg(default_value = ".", help = "directories (or archives) to scan")]
+    directories: Vec<String>,
+
+    #[arg(long, value_name = "PATH", conflicts_with = "no_config", help = "read defaults from this config file")]
+    config: Option<String>,
+
+    #[arg(long, help = "don't look for a .codump.toml")]
+    no_config: bool,
 
     #[arg(short, long, help = "copy output to clipboard")]
     clipboard: bool,
 
+    #[arg(
+        long,
+        conflicts_with_all = ["list", "encoding_report"],
+        help = "regenerate the dump whenever a file changes (implies --clipboard unless --output is given)"
+    )]
+    watch: bool,
+
     #[arg(
         short,
         long,
         default_value = DEFAULT_EXTENSIONS_STR,
-        help = "file extensions to include"
+        help = "file extensions to include; a leading + adds them to the defaults"
     )]
     extensions: String,
 
     #[arg(short, long, default_value_t = 100, help = "max file size in kb")]
     max_size: usize,
 
+    #[arg(long, help = "max file size in bytes (overrides --max-size)")]
+    max_bytes: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "EXT=KB",
+        value_parser = parse_size_for,
+        help = "max file size in kb for one extension, overriding --max-size; 0 leaves the extension out (repeatable)"
+    )]
+    max_size_for: Vec<(String, u64)>,
+
     #[arg(
         short = 'x',
         long,
@@ -124,219 +218,2512 @@ struct Cli {
     )]
     exclude: String,
 
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "only include files whose relative path matches a glob (repeatable)"
+    )]
+    include_glob: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "exclude files and directories whose relative path matches a glob (repeatable)"
+    )]
+    exclude_glob: Vec<String>,
+
+    #[arg(long, value_name = "N", help = "only descend N directories below the root (0: top-level entries only)")]
+    max_depth: Option<usize>,
+
     #[arg(long, default_value_t = 1000, help = "maximum files to include")]
     max_files: usize,
 
-    #[arg(short, long, help = "enable debug logging")]
-    verbose: bool,
-}
+    #[arg(long, value_name = "NAME", help = "display the scan root under this name")]
+    path_root_alias: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = PathStyle::Relative,
+        conflicts_with = "path_root_alias",
+        help = "how paths in the tree and file headers are written"
+    )]
+    path_style: PathStyle,
 
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "summarize matching directories instead of dumping their files (repeatable)"
+    )]
+    summarize_dirs_glob: Vec<String>,
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-    env_logger::Builder::new()
-        .filter_level(if cli.verbose { log::LevelFilter::Debug } else { log::LevelFilter::Warn })
-        .init();
+    #[arg(long, help = "normalize file content to unicode nfc (changes bytes vs. disk)")]
+    nfc: bool,
 
-    debug!(target: "dumpcode", "cli args: {:?}", cli);
+    #[arg(long, value_enum, default_value_t = Format::Markdown, help = "output format")]
+    format: Format,
 
-    let extensions_vec = cli.extensions.split(',').map(|s| s.trim().to_lowercase()).collect::<Vec<_>>();
-    let exclude_dirs_vec = cli.exclude.split(',').map(|s| s.trim()).collect::<Vec<_>>();
+    #[arg(
+        long,
+        value_name = "STR",
+        help = "layout of each file block, with {path}, {lang}, {content}, {size}, {ext} and {fence}"
+    )]
+    template: Option<String>,
 
-    let output = generate_dump(
-        &cli.directory,
-        &extensions_vec,
-        cli.max_size,
-        &exclude_dirs_vec,
-        cli.max_files,
-    )?;
+    #[arg(long, value_enum, conflicts_with = "template", help = "a named --template")]
+    preset: Option<Preset>,
 
-    if cli.clipboard {
-        set_clipboard(&output).context("failed to copy output to clipboard")?;
-        println!("Code dump copied to clipboard");
-    } else {
-        println!("{}", output);
-    }
+    #[arg(short, long, value_name = "PATH", help = "write output to a file (`-` for stdout)")]
+    output: Option<String>,
 
-    Ok(())
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        requires = "output",
+        help = "write --output as numbered parts of at most SIZE each, e.g. 80kb"
+    )]
+    split: Option<u64>,
+
+    #[arg(long, requires = "output", help = "start the --output file with a utf-8 bom")]
+    write_bom: bool,
+
+    #[arg(long, value_name = "CMD", help = "pipe output into a shell command")]
+    pipe_to: Option<String>,
+
+    #[arg(long, help = "include hidden files and directories (--exclude still applies)")]
+    hidden: bool,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "re-include hidden paths matching a glob (repeatable)"
+    )]
+    allow_hidden: Vec<String>,
+
+    #[arg(long, help = "skip files that aren't valid utf-8 instead of decoding them")]
+    skip_non_utf8: bool,
+
+    #[arg(long, help = "dump files that look binary instead of skipping them")]
+    include_binary: bool,
+
+    #[arg(long, help = "skip files that look minified or generated instead of noting it in their header")]
+    skip_generated: bool,
+
+    #[arg(
+        long,
+        visible_alias = "dry-run",
+        help = "list the files that would be dumped, with sizes, without reading them"
+    )]
+    list: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["list", "encoding_report", "mermaid_deps"],
+        help = "output only the project structure, without reading any file"
+    )]
+    tree_only: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["list", "encoding_report"],
+        help = "pick the files to dump from a checklist of everything that matched"
+    )]
+    select: bool,
+
+    #[arg(long, help = "report each file's detected encoding instead of dumping")]
+    encoding_report: bool,
+
+    #[arg(long, help = "only dump files git reports as changed or untracked")]
+    changed: bool,
+
+    #[arg(long, value_name = "REF", default_value = "HEAD", requires = "changed", help = "ref --changed compares against")]
+    base: String,
+
+    #[arg(long, help = "don't apply .hgignore/svn:ignore rules")]
+    no_vcs_ignore: bool,
+
+    #[arg(
+        long,
+        overrides_with = "no_respect_gitignore",
+        help = "skip files ignored by .gitignore, .ignore and git excludes (the default)"
+    )]
+    respect_gitignore: bool,
+
+    #[arg(long, overrides_with = "respect_gitignore", help = "don't apply .gitignore rules")]
+    no_respect_gitignore: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "elide tree lines longer than N columns (defaults to the terminal width)"
+    )]
+    tree_width: Option<usize>,
+
+    #[arg(long, help = "annotate tree directories with the number of included files below them")]
+    dir_counts: bool,
+
+    #[arg(long, help = "keep excluded directories in the tree as a collapsed [excluded] node")]
+    show_excluded: bool,
+
+    #[arg(long, help = "start the dump with a mermaid graph of imports between included files")]
+    mermaid_deps: bool,
+
+    #[arg(
+        long,
+        value_name = "LINES",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "split files longer than LINES lines into numbered parts"
+    )]
+    chunk_large_files: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "dump the files listed in PATH (one relative path per line) first, in that order"
+    )]
+    order_file: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with = "changed",
+        help = "dump exactly the files listed in PATH (one relative path per line, - for stdin) instead of scanning"
+    )]
+    files_from: Option<String>,
+
+    #[arg(long, help = "show each file's unix permissions in its header")]
+    show_perms: bool,
+
+    #[arg(long, help = "show each file's last modification time in its header")]
+    show_mtime: bool,
+
+    #[arg(long, help = "show the author of each file's last git commit in its header")]
+    show_git_info: bool,
+
+    #[arg(long, help = "append a checksum of the tree structure to the output")]
+    tree_checksum: bool,
+
+    #[arg(long, help = "warn about files that mix tab and space indentation")]
+    warn_mixed_indent: bool,
+
+    #[arg(long, help = "also include related sibling files (headers, tests) of included files")]
+    bundle_siblings: bool,
+
+    #[arg(
+        long,
+        requires = "anonymize_pattern",
+        help = "experimental: replace identifiers matching --anonymize-pattern with stable pseudonyms"
+    )]
+    anonymize: bool,
+
+    #[arg(long, help = "include files over the size limit, cut to their first and last lines")]
+    truncate: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 50,
+        requires = "truncate",
+        help = "lines kept at each end of a --truncate'd file"
+    )]
+    truncate_lines: usize,
+
+    #[arg(long, help = "prefix each line of file content with its line number")]
+    line_numbers: bool,
+
+    #[arg(long, help = "dump identical files once, pointing later copies at the first")]
+    dedup: bool,
+
+    #[arg(long, value_name = "REGEX", help = "only dump the lines of each file that match a regex")]
+    grep: Option<Regex>,
+
+    #[arg(
+        short = 'C',
+        long,
+        value_name = "N",
+        default_value_t = 0,
+        requires = "grep",
+        help = "lines of context to show around each --grep match"
+    )]
+    context: usize,
+
+    #[arg(long, help = "replace likely secrets (keys, tokens, private keys) with a placeholder")]
+    redact: bool,
+
+    #[arg(
+        long,
+        value_name = "REGEX",
+        requires = "redact",
+        help = "extra pattern to redact, replacing its `value` group if it has one (repeatable)"
+    )]
+    redact_pattern: Vec<Regex>,
+
+    #[arg(long, help = "remove comments and extra blank lines from file content (heuristic)")]
+    strip_comments: bool,
+
+    #[arg(long, value_name = "REGEX", help = "identifiers to anonymize")]
+    anonymize_pattern: Option<Regex>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "anonymize",
+        help = "write the pseudonym mapping to a file"
+    )]
+    anonymize_map: Option<String>,
+
+    #[arg(long, help = "only trust a shebang when the file is executable")]
+    shebang_exec_check: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "stop adding files once the estimated token count would exceed N"
+    )]
+    max_tokens: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "stop adding files once the output would grow past SIZE, e.g. 5mb"
+    )]
+    max_output: Option<u64>,
+
+    #[arg(
+        long,
+        value_enum,
+        requires = "max_tokens",
+        help = "how to pack files into --max-tokens instead of taking them in order"
+    )]
+    pack: Option<Pack>,
+
+    #[arg(long, value_name = "N", help = "fail if fewer than N files end up in the dump")]
+    min_files: Option<usize>,
+
+    #[arg(long, help = "fail on the first file that can't be read, or if --max-files left any out")]
+    strict: bool,
+
+    #[arg(long, help = "print the estimated token count of the output (per file with --verbose)")]
+    show_tokens: bool,
+
+    #[arg(long, help = "print a summary of the dump to stderr")]
+    stats: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = MaxFilesMode::Hard,
+        help = "which files to keep when --max-files is hit"
+    )]
+    max_files_mode: MaxFilesMode,
+
+    #[arg(long, value_enum, default_value_t = SortKey::Path, help = "order of files in the tree and dump")]
+    sort: SortKey,
+
+    // debugging aid: re-reads the generated dump and fails if it disagrees with what was written
+    #[arg(long, hide = true)]
+    self_check: bool,
+
+    #[arg(
+        short,
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "read and process at most N files at once (default: one per cpu)"
+    )]
+    jobs: Option<u64>,
+
+    #[arg(short, long, help = "enable debug logging")]
+    verbose: bool,
 }
 
-fn generate_dump(
-    directory: &str,
-    extensions: &[String],
-    max_size_kb: usize,
-    exclude_dirs: &[&str],
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum MaxFilesMode {
+    /// keep the first files in walk order
+    Hard,
+    /// keep the largest files
+    LargestFirst,
+    /// keep the smallest files, fitting as many as possible
+    SmallestFirst,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum PathStyle {
+    /// relative to the scan root
+    Relative,
+    /// full paths
+    Absolute,
+    /// relative to the project the scan root is in, going by the nearest `.git` or `Cargo.toml`
+    FromRoot,
+}
+
+/// how entries are ordered within each directory. the tree stays a tree either way, so
+/// this only ever reorders siblings
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum SortKey {
+    /// case-insensitive by name, directories mixed in with files
+    Path,
+    /// directories first, then files smallest to largest
+    Size,
+    /// directories first, then files grouped by extension
+    Ext,
+}
+
+#[derive(Debug)]
+struct DumpOptions {
+    extensions: Vec<String>,
+    max_bytes: u64,
+    // --max-size-for limits by extension, which take the place of max_bytes
+    max_bytes_for: HashMap<String, u64>,
+    exclude_dirs: Vec<String>,
+    // an empty include set lets everything through
+    include_globs: GlobSet,
+    exclude_globs: GlobSet,
+    max_depth: Option<usize>,
     max_files: usize,
-) -> Result<String> {
-    let mut output = String::new();
-    let (tree, included_files) =
-        generate_tree_view(directory, extensions, max_size_kb, exclude_dirs, max_files)?;
-    output.push_str("# project structure\n\n");
-    output.push_str(&tree);
-    output.push_str("\n\n");
-
-    let base = Utf8Path::new(directory);
-    let files_output: Result<Vec<String>> = included_files
-        .par_iter()
-        .map(|relative_path| {
-            let start_time = std::time::Instant::now();
-            let full_path = base.join(relative_path);
-
-            let mut file = fs::File::open(full_path.as_std_path())?;
-            let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer)?;
-
-            let content = match String::from_utf8(buffer) {
-                Ok(s) => s,
-                Err(e) => {
-                    warn!("non-utf8 file skipped: {} ({})", relative_path, e);
-                    return Ok(String::new());
-                }
-            };
+    max_files_mode: MaxFilesMode,
+    sort: SortKey,
+    root_alias: Option<String>,
+    path_style: PathStyle,
+    // with several roots, each root's paths are shown under its own name
+    path_prefix: Option<String>,
+    hidden: HiddenFilter,
+    vcs_ignore: Option<VcsIgnore>,
+    // with --changed, only these files (and the directories holding them) are walked
+    changed: Option<ChangedFiles>,
+    selection: Option<Selection>,
+    respect_gitignore: bool,
+    summarize_dirs: GlobSet,
+    bundle_siblings: bool,
+    shebang_exec_check: bool,
+    nfc: bool,
+    skip_non_utf8: bool,
+    include_binary: bool,
+    skip_generated: bool,
+    // abort on unreadable files rather than leaving them out
+    strict: bool,
+    tree_only: bool,
+    redactor: Option<Redactor>,
+    strip_comments: bool,
+    anonymize_pattern: Option<Regex>,
+    // lines kept at each end of files over max_bytes; none means those files are skipped
+    truncate_lines: Option<usize>,
+    line_numbers: bool,
+    dedup: bool,
+    grep: Option<Regex>,
+    context: usize,
+    max_tokens: Option<usize>,
+    // bytes left for this root under --max-output, with room kept for the cut marker
+    max_output: Option<u64>,
+    pack: Option<Pack>,
+    warn_mixed_indent: bool,
+    tree_width: Option<usize>,
+    dir_counts: bool,
+    show_excluded: bool,
+    mermaid_deps: bool,
+    show_perms: bool,
+    show_mtime: bool,
+    show_git_info: bool,
+    chunk_lines: Option<usize>,
+    order: Option<Vec<Utf8PathBuf>>,
+    // json and --self-check need each file's content after its block is written
+    keep_contents: bool,
+    // replaces the markdown file block layout
+    template: Option<Template>,
+    // replaces the walk: only these files and their directories, with no extension filter
+    files_from: Option<Vec<Utf8PathBuf>>,
+    tree_checksum: bool,
+    format: Format,
+}
+
+/// decides which dotfiles make it into the walk. without `--hidden` anything under a
+/// dot-prefixed component is skipped unless an `--allow-hidden` glob re-includes it
+#[derive(Debug)]
+struct HiddenFilter {
+    include_all: bool,
+    allow: GlobSet,
+    // literal leading components of each allow glob, used to decide which hidden
+    // directories are worth descending into
+    allow_prefixes: Vec<Vec<String>>,
+}
 
-            let ext = relative_path.extension().unwrap_or("").to_lowercase();
-            let lang = language_for_extension(&ext, &content);
-            debug!("processed {} in {:?}", relative_path, start_time.elapsed());
-            let file_dump = format!(
-                "# file: {}\n\n```{}\n{}\n```\n\n",
-                relative_path, lang, content
+impl HiddenFilter {
+    fn new(include_all: bool, patterns: &[String]) -> Result<Self> {
+        let mut builder = GlobSetBuilder::new();
+        let mut allow_prefixes = Vec::new();
+        for pattern in patterns {
+            let glob =
+                Glob::new(pattern).with_context(|| format!("invalid --allow-hidden glob: {}", pattern))?;
+            builder.add(glob);
+            allow_prefixes.push(
+                pattern
+                    .split('/')
+                    .take_while(|c| !c.contains(['*', '?', '[', '{']))
+                    .map(str::to_string)
+                    .collect(),
             );
-            Ok(file_dump)
-        })
-        .collect();
+        }
 
-    for file_out in files_output? {
-        output.push_str(&file_out);
+        Ok(Self { include_all, allow: builder.build()?, allow_prefixes })
     }
 
-    Ok(output)
+    fn allows(&self, rel_path: &Path, is_dir: bool) -> bool {
+        let components: Vec<_> = rel_path.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+        if self.include_all || !components.iter().any(|c| c.starts_with('.')) {
+            return true;
+        }
+        if self.allow.is_match(rel_path) {
+            return true;
+        }
+
+        // a hidden directory is kept while it still agrees with the literal part of some glob
+        is_dir
+            && self
+                .allow_prefixes
+                .iter()
+                .any(|prefix| prefix.iter().zip(&components).all(|(literal, component)| literal == component))
+    }
 }
 
-fn generate_tree_view(
-    path: &str,
-    extensions: &[String],
-    max_size_kb: usize,
-    exclude_dirs: &[&str],
-    max_files: usize,
-) -> Result<(String, Vec<Utf8PathBuf>)> {
-    let mut file_count = 0;
-    let mut tree = String::new();
-    let mut files = Vec::new();
+#[derive(Debug)]
+struct TreeView {
+    tree: String,
+    files: Vec<Utf8PathBuf>,
+    summaries: Vec<DirSummary>,
+    // hash of the rendered paths only, so it ignores sizes and content
+    checksum: u64,
+    skipped: SkipReport,
+    // where each file's line in `tree` ends, for notes added once the contents are known
+    line_ends: Vec<(Utf8PathBuf, usize)>,
+}
 
-    let base = Utf8Path::new(path).file_name().unwrap_or(path);
-    tree.push_str(&format!("{}/\n", base));
+#[derive(Debug)]
+struct DirSummary {
+    path: Utf8PathBuf,
+    files: usize,
+    bytes: u64,
+    languages: BTreeSet<&'static str>,
+}
 
-    let walker = WalkDir::new(path)
-        .min_depth(1)
-        .follow_links(false)
-        .same_file_system(true)
-        .into_iter()
-        .filter_entry(|e| {
-            let name = e.file_name().to_string_lossy();
-            !exclude_dirs.iter().any(|d| name == *d)
-        });
+/// one file as it appears in the dump, after any content transforms
+#[derive(Debug)]
+struct DumpFile {
+    path: Utf8PathBuf,
+    language: String,
+    // as read from disk or the archive, before any transform
+    size_bytes: u64,
+    // only filled in with --show-perms
+    mode: Option<u32>,
+    // the encoding the content was converted from, when it wasn't utf-8 on disk
+    decoded_from: Option<&'static str>,
+    // only filled in with --show-mtime and --show-git-info
+    modified: Option<SystemTime>,
+    author: Option<String>,
+    // with --dedup, the earlier file with the same content, which this one's block points to
+    duplicate_of: Option<Utf8PathBuf>,
+    // set when the file looks minified or generated, which its header then says
+    generated: Option<Generated>,
+    content: String,
+}
 
-    for entry in walker {
-        let entry = entry?;
-        if entry.path_is_symlink() {
-            warn!("skipping symlink: {}", entry.path().display());
-            continue;
-        }
+/// why a file looks like it wasn't written by hand
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Generated {
+    Minified,
+    Marked,
+}
 
-        let entry_path = entry.path();
-        let file_name = entry.file_name().to_string_lossy();
-        let is_excluded = entry.depth() > 0 && exclude_dirs.iter().any(|d| file_name == *d);
-        if is_excluded {
-            continue;
-        }
-        if file_count >= max_files {
-            break;
+impl Generated {
+    fn label(self) -> &'static str {
+        match self {
+            Generated::Minified => "minified",
+            Generated::Marked => "generated",
         }
+    }
 
-        let depth = entry.depth();
-        let indent = "  ".repeat(depth - 1);
-        let prefix = if depth == 1 { "├── " } else { "└── " };
-        if entry.file_type().is_file() {
-            let metadata = entry.metadata()?;
-            let size_kb = metadata.len() / 1024;
-            let ext = entry_path
-                .extension()
-                .and_then(|e| e.to_str())
-                .unwrap_or("")
-                .to_lowercase();
-            if extensions.contains(&ext) && size_kb <= max_size_kb as u64 {
-                file_count += 1;
-                let rel_path = Utf8Path::from_path(entry_path)
-                    .and_then(|p| p.strip_prefix(path).ok())
-                    .unwrap_or_else(|| Utf8Path::from_path(entry_path).unwrap())
-                    .to_owned();
-                tree.push_str(&format!("{}{}{} [{}kb]\n", indent, prefix, rel_path, size_kb));
-                files.push(rel_path.clone());
-            }
-        } else if entry.file_type().is_dir() {
-            let rel_path = Utf8Path::from_path(entry_path)
-                .and_then(|p| p.strip_prefix(path).ok())
-                .unwrap_or_else(|| Utf8Path::from_path(entry_path).unwrap())
-                .to_owned();
-            tree.push_str(&format!("{}{}{}/\n", indent, prefix, rel_path));
+    fn skip_reason(self) -> SkipReason {
+        match self {
+            Generated::Minified => SkipReason::Minified,
+            Generated::Marked => SkipReason::Generated,
         }
     }
+}
 
-    Ok((tree, files))
+#[derive(Debug)]
+struct Dump {
+    output: String,
+    // offsets in `output` where a file block (or other piece --split must keep whole) starts,
+    // with the file it belongs to. the text before the first one is the tree and the like
+    breaks: Vec<(usize, Option<Utf8PathBuf>)>,
+    stats: DumpStats,
+    files: Vec<DumpFile>,
+    // the pieces again, for formats that lay out the whole document themselves
+    tree: String,
+    summaries: Vec<DirSummary>,
+    graph: Option<String>,
+    checksum: Option<u64>,
 }
 
-fn language_for_extension(ext: &str, content: &str) -> &'static str {
-    LANG_MAP.get(ext).copied().unwrap_or_else(|| {
-        if content.starts_with("#!") {
-            detect_shebang(content)
-        } else if ext.is_empty() {
-            detect_special_file(content)
-        } else {
-            ""
-        }
-    })
+/// what became of one file read for the dump
+enum Outcome {
+    // with the content hash when --dedup wants it
+    Dumped(Box<DumpFile>, Vec<Utf8PathBuf>, Option<u64>),
+    Skipped(SkipReason),
+    // left out on purpose, like a file without a --grep match
+    Filtered,
 }
 
-fn detect_shebang(content: &str) -> &'static str {
-    lazy_static::lazy_static! {
-        static ref SHEBANG_RE: Regex = Regex::new(r"^#!\s*/usr/bin/env\s+(\w+)|^#!\s*/.*/(\w+)").unwrap();
+/// the output of one root as it's put together, file block by file block
+struct Assembly {
+    output: String,
+    breaks: Vec<(usize, Option<Utf8PathBuf>)>,
+    files: Vec<DumpFile>,
+    stats: DumpStats,
+    // set once a block didn't fit under --max-output
+    output_cut: bool,
+    // by relative path, what the tree should say about a --dedup duplicate or a file
+    // --skip-generated left out
+    tree_notes: HashMap<Utf8PathBuf, String>,
+    // bytes the tree notes and the sections that only go in at the end (the deps graph, the
+    // checksum) will add, which --max-output has to leave room for
+    pending_bytes: u64,
+}
+
+fn duplicate_note(first: &Utf8Path) -> String {
+    format!("[duplicate of {}]", first)
+}
+
+/// how much `note` adds to its tree line, as `annotate_tree` puts it there
+fn tree_note_len(note: &str) -> usize {
+    note.len() + 1
+}
+
+/// the tokens `note` adds to its tree line
+fn tree_note_cost(note: &str) -> usize {
+    tokens::estimate_tokens(&format!(" {}", note))
+}
+
+/// a file block waiting for its place in the output
+struct Placement {
+    rel_path: Utf8PathBuf,
+    file: DumpFile,
+    block: String,
+    // the block's estimated tokens, and for a duplicate its tree note's as well
+    cost: usize,
+    // the content hash, with --dedup
+    hash: Option<u64>,
+}
+
+impl Placement {
+    fn new(rel_path: Utf8PathBuf, file: DumpFile, block: String, hash: Option<u64>) -> Self {
+        let mut placement = Placement { rel_path, file, block, cost: 0, hash };
+        placement.cost = placement.estimate_cost();
+        placement
     }
 
-    if let Some(first_line) = content.lines().next() {
-        if let Some(caps) = SHEBANG_RE.captures(first_line) {
-            let lang = caps.get(1).or_else(|| caps.get(2)).map(|m| m.as_str());
-            return match lang {
-                Some("python3") | Some("python") => "python",
-                Some("ruby") => "ruby",
-                Some("node") | Some("nodejs") => "javascript",
-                Some("bash") | Some("sh") => "bash",
-                Some("perl") => "perl",
-                Some("php") => "php",
-                Some("lua") => "lua",
-                Some("Rscript") => "r",
-                _ => "",
-            };
+    fn estimate_cost(&self) -> usize {
+        let note = self.file.duplicate_of.as_ref().map_or(0, |first| tree_note_cost(&duplicate_note(first)));
+        tokens::estimate_tokens(&self.block) + note
+    }
+
+    /// points a --dedup duplicate at the copy of its content that's actually in the dump.
+    /// when the budget dropped every earlier copy, this one becomes the first copy instead
+    fn settle_duplicate(&mut self, first_copies: &HashMap<u64, Utf8PathBuf>, opts: &DumpOptions) {
+        let (Some(hash), Some(tentative)) = (self.hash, &self.file.duplicate_of) else {
+            return;
+        };
+        let first = first_copies.get(&hash);
+        if first == Some(tentative) {
+            return;
         }
+        self.file.duplicate_of = first.cloned();
+        self.block = format::file_block(opts.format, &self.file, opts.chunk_lines, opts.template.as_ref());
+        self.cost = self.estimate_cost();
     }
-    ""
 }
 
-fn detect_special_file(content: &str) -> &'static str {
-    if content.contains("FROM ") {
-        "dockerfile"
-    } else if content.contains("JAVA_HOME") {
-        "properties"
-    } else {
-        ""
+impl Assembly {
+    /// whether `block` would take the output past --max-output. the dump stops at the
+    /// first block that doesn't fit, so nothing after it goes in either
+    fn over_output_cap(&mut self, placement: &Placement, opts: &DumpOptions) -> bool {
+        let Some(cap) = opts.max_output else {
+            return false;
+        };
+        let note = placement.file.duplicate_of.as_ref().map_or(0, |first| tree_note_len(&duplicate_note(first)));
+        let grown = self.output.len() as u64 + self.pending_bytes + (placement.block.len() + note) as u64;
+        self.output_cut |= grown > cap;
+        self.output_cut
     }
-}
 
-fn set_clipboard(text: &str) -> Result<()> {
-    let mut attempts = 0;
+    fn note(&mut self, rel_path: Utf8PathBuf, note: String) {
+        self.pending_bytes += tree_note_len(&note) as u64;
+        self.tree_notes.insert(rel_path, note);
+    }
+
+    /// adds a block to the output. a first copy is only recorded in `first_copies` here,
+    /// once it's certain to be in the dump, so no duplicate can point at a dropped file
+    fn push(&mut self, placement: Placement, first_copies: &mut HashMap<u64, Utf8PathBuf>, opts: &DumpOptions) {
+        let Placement { rel_path, mut file, block, cost, hash } = placement;
+        match &file.duplicate_of {
+            Some(first) => {
+                debug!("{} is a duplicate of {}", file.path, first);
+                self.stats.duplicates += 1;
+                self.stats.dedup_bytes += file.content.len() as u64;
+                self.note(rel_path, duplicate_note(first));
+                file.content = String::new();
+            }
+            None => {
+                if let Some(hash) = hash {
+                    first_copies.entry(hash).or_insert_with(|| file.path.clone());
+                }
+            }
+        }
+        self.stats.files += 1;
+        if file.duplicate_of.is_none() {
+            self.stats.content_bytes += file.size_bytes;
+        }
+        self.stats.content_tokens += cost;
+        match file.generated {
+            Some(Generated::Minified) => self.stats.minified += 1,
+            Some(Generated::Marked) => self.stats.generated += 1,
+            None => {}
+        }
+        self.stats.file_tokens.push((file.path.clone(), cost));
+        if opts.warn_mixed_indent {
+            if let Some(line) = first_mixed_indent(&file.content) {
+                warn!("mixed tab/space indentation in {} (first at line {})", file.path, line);
+                self.stats.mixed_indent.push((file.path.clone(), line));
+            }
+        }
+        self.breaks.push((self.output.len(), Some(file.path.clone())));
+        self.output.push_str(&block);
+        if !opts.keep_contents {
+            file.content = String::new();
+        }
+        self.files.push(file);
+    }
+}
+
+/// swaps identifiers matching a regex for stable `IdentN` names. purely textual, it
+/// knows nothing about the language, so it will also rewrite matches in strings
+#[derive(Debug)]
+struct Anonymizer {
+    pattern: Regex,
+    assigned: HashMap<String, String>,
+    mapping: Vec<(String, String)>,
+}
+
+impl Anonymizer {
+    fn new(pattern: &Regex) -> Self {
+        Anonymizer { pattern: pattern.clone(), assigned: HashMap::new(), mapping: Vec::new() }
+    }
+
+    fn apply(&mut self, content: &str) -> String {
+        let pattern = self.pattern.clone();
+        pattern
+            .replace_all(content, |caps: &regex::Captures| {
+                let original = &caps[0];
+                if let Some(pseudonym) = self.assigned.get(original) {
+                    return pseudonym.clone();
+                }
+                let pseudonym = format!("Ident{}", self.mapping.len() + 1);
+                self.assigned.insert(original.to_string(), pseudonym.clone());
+                self.mapping.push((original.to_string(), pseudonym.clone()));
+                pseudonym
+            })
+            .into_owned()
+    }
+}
+
+#[derive(Debug, Default)]
+struct DumpStats {
+    files: usize,
+    content_bytes: u64,
+    content_tokens: usize,
+    // tree, summaries, graph and checksum: everything that isn't a file block
+    scaffolding_tokens: usize,
+    // estimated cost of each dumped file's block (and a duplicate's tree note), in output order
+    file_tokens: Vec<(Utf8PathBuf, usize)>,
+    skipped: SkipReport,
+    // files --dedup cut down to a pointer, and the content bytes that saved
+    duplicates: usize,
+    dedup_bytes: u64,
+    // dumped files that look generated, each with a note in its header
+    generated: usize,
+    minified: usize,
+    // file and 1-based line of the first inconsistent indent
+    mixed_indent: Vec<(Utf8PathBuf, usize)>,
+}
+
+impl DumpStats {
+    /// adds another root's numbers to these
+    fn absorb(&mut self, other: DumpStats) {
+        self.files += other.files;
+        self.content_bytes += other.content_bytes;
+        self.content_tokens += other.content_tokens;
+        self.scaffolding_tokens += other.scaffolding_tokens;
+        self.file_tokens.extend(other.file_tokens);
+        self.skipped.absorb(other.skipped);
+        self.duplicates += other.duplicates;
+        self.dedup_bytes += other.dedup_bytes;
+        self.generated += other.generated;
+        self.minified += other.minified;
+        self.mixed_indent.extend(other.mixed_indent);
+    }
+}
+
+fn main() -> Result<()> {
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    env_logger::Builder::new()
+        .filter_level(if cli.verbose { log::LevelFilter::Debug } else { log::LevelFilter::Warn })
+        .init();
+
+    let config_path = match &cli.config {
+        _ if cli.no_config => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => Config::discover(Path::new(&cli.directories[0])),
+    };
+    if let Some(path) = config_path {
+        debug!("using config file {}", path.display());
+        apply_config(&mut cli, &matches, Config::load(&path)?);
+    }
+
+    debug!(target: "dumpcode", "cli args: {:?}", cli);
+
+    if cli.directories.len() > 1 && cli.path_root_alias.is_some() {
+        anyhow::bail!("--path-root-alias can only be used with a single directory");
+    }
+    let custom_template = cli.template.is_some() || cli.preset.is_some_and(|p| p != Preset::Markdown);
+    if custom_template && cli.format != Format::Markdown {
+        anyhow::bail!("--template and --preset only apply to --format markdown");
+    }
+    if custom_template && cli.self_check {
+        anyhow::bail!("--self-check only understands the built-in file blocks, not a --template");
+    }
+    if cli.split.is_some() && (cli.output.as_deref() == Some("-") || cli.format == Format::Json) {
+        anyhow::bail!("--split needs --output to be a file path and a markdown or rag --format");
+    }
+    if cli.max_output.is_some() && cli.format == Format::Json {
+        anyhow::bail!("--max-output can't cut a json document short; use a markdown or rag --format");
+    }
+    if cli.directories.len() > 1 && cli.files_from.is_some() {
+        anyhow::bail!("--files-from can only be used with a single directory, which its paths are relative to");
+    }
+    let prefixes = root_prefixes(&cli.directories, cli.path_style)?;
+    // printing a fresh dump to the terminal on every save isn't much use
+    if cli.watch && !cli.clipboard && cli.output.is_none() && cli.pipe_to.is_none() {
+        cli.clipboard = true;
+    }
+
+    let mut opts = dump_options(&cli)?;
+
+    if cli.show_perms && cfg!(not(unix)) && !cli.directories.iter().all(|d| source::is_archive(d)) {
+        debug!("--show-perms has no effect here: this platform has no unix permissions");
+    }
+
+    if cli.encoding_report {
+        let mut rows = Vec::new();
+        for (directory, prefix) in cli.directories.iter().zip(&prefixes) {
+            opts.vcs_ignore = discover_vcs_ignore(&cli, directory)?;
+            opts.changed = changed_files(&cli, directory)?;
+            opts.path_prefix = prefix.clone();
+            let source = source::open(directory, opts.respect_gitignore, opts.strict)?;
+            let view = generate_tree_view(directory, source.as_ref(), &opts)?;
+            rows.extend(encoding_rows(source.as_ref(), &view.files, &opts)?);
+        }
+        print!("{}", encoding_report(rows));
+        return Ok(());
+    }
+
+    if cli.list {
+        if cli.clipboard || cli.output.is_some() || cli.pipe_to.is_some() {
+            warn!("--list only prints the file list; ignoring --clipboard, --output and --pipe-to");
+        }
+        let (mut count, mut content_bytes, mut output_bytes) = (0, 0, 0);
+        let mut skipped = SkipReport::default();
+        for (directory, prefix) in cli.directories.iter().zip(&prefixes) {
+            opts.vcs_ignore = discover_vcs_ignore(&cli, directory)?;
+            opts.changed = changed_files(&cli, directory)?;
+            opts.path_prefix = prefix.clone();
+            opts.max_files = cli.max_files.saturating_sub(count);
+            let source = source::open(directory, opts.respect_gitignore, opts.strict)?;
+            let mut view = generate_tree_view(directory, source.as_ref(), &opts)?;
+            skipped.absorb(std::mem::take(&mut view.skipped));
+            output_bytes += format::tree_section(opts.format, &view.tree).len() as u64;
+            for rel_path in &view.files {
+                let size = source.file_size(rel_path).unwrap_or(0);
+                let path = display_path(&opts, rel_path);
+                println!("{:>10}  {}", format_size(size), path);
+                // an empty block of the same file is its header and fence overhead
+                let empty = DumpFile {
+                    path,
+                    language: String::new(),
+                    size_bytes: 0,
+                    mode: None,
+                    decoded_from: None,
+                    modified: None,
+                    author: None,
+                    duplicate_of: None,
+                    generated: None,
+                    content: String::new(),
+                };
+                output_bytes += size + format::file_block(opts.format, &empty, None, opts.template.as_ref()).len() as u64;
+                content_bytes += size;
+                count += 1;
+            }
+        }
+        println!(
+            "{} files, {} of content, ~{} estimated output",
+            count,
+            format_size(content_bytes),
+            format_size(output_bytes)
+        );
+        if let Some(summary) = skipped.summary(cli.max_files) {
+            eprintln!("{}", summary);
+            if cli.verbose {
+                eprint!("{}", skipped.details());
+            }
+        }
+        return Ok(());
+    }
+
+    if cli.select {
+        if select::is_interactive() {
+            let candidates = select_candidates(&cli, &mut opts, &prefixes)?;
+            opts.selection = Some(select::pick(&candidates)?);
+        } else {
+            warn!("--select needs a terminal to draw on; dumping every matched file");
+        }
+    }
+
+    // a pool of its own, so --jobs bounds every parallel step of the dump
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(cli.jobs.map_or(0, |jobs| jobs as usize))
+        .build()
+        .context("failed to start the worker threads")?;
+    pool.install(|| dump_and_deliver(&cli, &mut opts, &prefixes))?;
+    if cli.watch {
+        let is_own_output = own_outputs(&cli);
+        let exclude_dirs = opts.exclude_dirs.clone();
+        watch::watch(&cli.directories, &exclude_dirs, &is_own_output, || {
+            pool.install(|| dump_and_deliver(&cli, &mut opts, &prefixes))
+        })?;
+    }
+
+    Ok(())
+}
+
+/// the dump settings `cli` asks for. the per-root ones (vcs ignores, changed files, the
+/// path prefix) are filled in for each root as it's dumped
+fn dump_options(cli: &Cli) -> Result<DumpOptions> {
+    Ok(DumpOptions {
+        extensions: parse_extensions(&cli.extensions),
+        max_bytes: cli.max_bytes.unwrap_or(cli.max_size as u64 * 1024),
+        max_bytes_for: cli.max_size_for.iter().cloned().collect(),
+        exclude_dirs: cli.exclude.split(',').map(|s| s.trim().to_string()).collect(),
+        include_globs: build_globset(&cli.include_glob, "--include-glob")?,
+        exclude_globs: build_globset(&cli.exclude_glob, "--exclude-glob")?,
+        max_depth: cli.max_depth,
+        max_files: cli.max_files,
+        max_files_mode: cli.max_files_mode,
+        sort: cli.sort,
+        root_alias: cli.path_root_alias.clone(),
+        path_style: cli.path_style,
+        path_prefix: None,
+        hidden: HiddenFilter::new(cli.hidden, &cli.allow_hidden)?,
+        vcs_ignore: None,
+        changed: None,
+        selection: None,
+        respect_gitignore: cli.respect_gitignore || !cli.no_respect_gitignore,
+        summarize_dirs: build_globset(&cli.summarize_dirs_glob, "--summarize-dirs-glob")?,
+        bundle_siblings: cli.bundle_siblings,
+        shebang_exec_check: cli.shebang_exec_check,
+        nfc: cli.nfc,
+        skip_non_utf8: cli.skip_non_utf8,
+        include_binary: cli.include_binary,
+        skip_generated: cli.skip_generated,
+        tree_only: cli.tree_only,
+        strict: cli.strict,
+        redactor: cli.redact.then(|| Redactor::new(&cli.redact_pattern)),
+        strip_comments: cli.strip_comments,
+        anonymize_pattern: if cli.anonymize { cli.anonymize_pattern.clone() } else { None },
+        truncate_lines: cli.truncate.then_some(cli.truncate_lines),
+        line_numbers: cli.line_numbers,
+        dedup: cli.dedup,
+        grep: cli.grep.clone(),
+        context: cli.context,
+        max_tokens: cli.max_tokens,
+        max_output: None,
+        pack: cli.pack,
+        warn_mixed_indent: cli.warn_mixed_indent,
+        tree_width: cli.tree_width.or_else(|| terminal_tree_width(cli)),
+        dir_counts: cli.dir_counts,
+        show_excluded: cli.show_excluded,
+        mermaid_deps: cli.mermaid_deps,
+        show_perms: cli.show_perms,
+        show_mtime: cli.show_mtime,
+        show_git_info: cli.show_git_info,
+        chunk_lines: cli.chunk_large_files.map(|n| n as usize),
+        order: cli.order_file.as_deref().map(|path| read_path_list(path, "--order-file")).transpose()?,
+        keep_contents: cli.format == Format::Json || cli.self_check,
+        template: match (&cli.template, cli.preset) {
+            (Some(template), _) => Some(Template::parse(template)?),
+            (None, preset) => preset.and_then(Preset::template).map(Template::parse).transpose()?,
+        },
+        files_from: cli.files_from.as_deref().map(|path| read_path_list(path, "--files-from")).transpose()?,
+        tree_checksum: cli.tree_checksum,
+        format: cli.format,
+    })
+}
+
+/// whether a changed path (canonical, as the watcher reports it) is one the dump wrote
+/// itself: the --output file, any of its --split parts or the --anonymize-map
+fn own_outputs(cli: &Cli) -> impl Fn(&Path) -> bool {
+    let written: Vec<PathBuf> = cli
+        .output
+        .iter()
+        .chain(&cli.anonymize_map)
+        .filter(|path| *path != "-")
+        .filter_map(|path| Path::new(path).canonicalize().ok())
+        .collect();
+    // how many parts there are changes from one refresh to the next, so they go by name
+    let parts = cli.split.and(cli.output.as_deref()).filter(|path| *path != "-").and_then(|output| {
+        let dir = Path::new(output).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        Some((dir.canonicalize().ok()?, output.to_string()))
+    });
+    move |path| {
+        written.iter().any(|written| written == path)
+            || parts.as_ref().is_some_and(|(dir, output)| {
+                path.parent() == Some(dir.as_path())
+                    && path.file_name().and_then(|name| name.to_str()).is_some_and(|name| is_part_name(name, output))
+            })
+    }
+}
+
+/// every file the dump would include, for --select to choose from
+fn select_candidates(cli: &Cli, opts: &mut DumpOptions, prefixes: &[Option<String>]) -> Result<Vec<Candidate>> {
+    let mut candidates = Vec::new();
+    for (directory, prefix) in cli.directories.iter().zip(prefixes) {
+        opts.vcs_ignore = discover_vcs_ignore(cli, directory)?;
+        opts.changed = changed_files(cli, directory)?;
+        opts.path_prefix = prefix.clone();
+        opts.max_files = cli.max_files.saturating_sub(candidates.len());
+        let source = source::open(directory, opts.respect_gitignore, opts.strict)?;
+        let view = generate_tree_view(directory, source.as_ref(), opts)?;
+        candidates.extend(view.files.iter().map(|rel_path| Candidate {
+            path: display_path(opts, rel_path),
+            size: source.file_size(rel_path).unwrap_or(0),
+        }));
+    }
+    Ok(candidates)
+}
+
+/// builds the dump from every root and sends it wherever the flags say
+fn dump_and_deliver(cli: &Cli, opts: &mut DumpOptions, prefixes: &[Option<String>]) -> Result<()> {
+    // the file and token limits are shared, so each root gets whatever the earlier ones left
+    let mut anonymizer = opts.anonymize_pattern.as_ref().map(Anonymizer::new);
+    let mut first_copies = HashMap::new();
+    let (mut output, mut stats, mut files) = (String::new(), DumpStats::default(), Vec::new());
+    let mut breaks = Vec::new();
+    let (mut trees, mut summaries, mut graphs, mut checksum) = (String::new(), Vec::new(), Vec::new(), None);
+    for (directory, prefix) in cli.directories.iter().zip(prefixes) {
+        opts.vcs_ignore = discover_vcs_ignore(cli, directory)?;
+        opts.changed = changed_files(cli, directory)?;
+        opts.path_prefix = prefix.clone();
+        opts.max_files = cli.max_files.saturating_sub(stats.files);
+        if !output.is_empty() {
+            breaks.push((output.len(), None));
+        }
+        // a lone root's prefix is only its --path-style, with no other roots to tell it from
+        if let Some(prefix) = prefix.as_ref().filter(|_| prefixes.len() > 1) {
+            let header = format::root_header(opts.format, prefix);
+            stats.scaffolding_tokens += tokens::estimate_tokens(&header);
+            output.push_str(&header);
+        }
+        let used_tokens = stats.content_tokens + stats.scaffolding_tokens;
+        opts.max_tokens = cli.max_tokens.map(|budget| budget.saturating_sub(used_tokens));
+        // once one root was cut short, later ones add nothing either
+        let cut = !stats.skipped.paths(SkipReason::OutputCap).is_empty();
+        let output_left = |cap: u64| if cut { 0 } else { cap.saturating_sub(OUTPUT_CUT_RESERVE + output.len() as u64) };
+        opts.max_output = cli.max_output.map(output_left);
+
+        let dump = generate_dump(directory, opts, &mut anonymizer, &mut first_copies)?;
+        breaks.extend(dump.breaks.into_iter().map(|(start, path)| (output.len() + start, path)));
+        output.push_str(&dump.output);
+        stats.absorb(dump.stats);
+        files.extend(dump.files);
+        trees.push_str(&dump.tree);
+        summaries.extend(dump.summaries);
+        graphs.extend(dump.graph);
+        // several roots fold into one checksum over each root's own
+        checksum = dump.checksum.map(|root| match checksum {
+            None => root,
+            Some(previous) => {
+                let mut combined = Fnv64::new();
+                combined.write(&format!("{:016x}{:016x}", previous, root));
+                combined.finish()
+            }
+        });
+    }
+    let cut = stats.skipped.paths(SkipReason::OutputCap).len();
+    if cut > 0 {
+        breaks.push((output.len(), None));
+        output.push_str(&format::output_cut_marker(opts.format, stats.files, cut));
+    }
+    if opts.format == Format::Json {
+        let pretty = cli.output.as_deref().is_some_and(|path| path != "-");
+        let graph = (!graphs.is_empty()).then(|| graphs.concat());
+        output = format::json_document(&trees, &summaries, graph.as_deref(), checksum, &files, pretty);
+    }
+    opts.max_tokens = cli.max_tokens;
+    let pseudonyms = anonymizer.map(|a| a.mapping).unwrap_or_default();
+    if cli.self_check {
+        self_check::verify(&output, &files, opts.format)?;
+    }
+    if let Some(summary) = stats.skipped.summary(cli.max_files) {
+        eprintln!("{}", summary);
+        if cli.verbose {
+            eprint!("{}", stats.skipped.details());
+        }
+    }
+    if cli.strict && stats.skipped.max_files_hit() {
+        anyhow::bail!("--max-files {} left files out of the dump, which --strict doesn't allow", cli.max_files);
+    }
+    if let Some(min_files) = cli.min_files.filter(|&min| stats.files < min) {
+        anyhow::bail!(
+            "only {} files were included but --min-files requires {}; check the --extensions, --exclude and size filters",
+            stats.files,
+            min_files
+        );
+    }
+    if let Some(map_path) = &cli.anonymize_map {
+        let map: String = pseudonyms
+            .iter()
+            .map(|(original, pseudonym)| format!("{}\t{}\n", pseudonym, original))
+            .collect();
+        fs::write(map_path, map).with_context(|| format!("failed to write {}", map_path))?;
+    }
+
+    if cli.clipboard {
+        set_clipboard(&output).context("failed to copy output to clipboard")?;
+        eprintln!("Code dump copied to clipboard");
+    }
+    if let Some(cmd) = &cli.pipe_to {
+        pipe_to_command(cmd, &output).with_context(|| format!("failed to pipe output to `{}`", cmd))?;
+    }
+    match cli.output.as_deref() {
+        Some("-") => println!("{}", output),
+        Some(path) => match cli.split {
+            Some(cap) => {
+                let parts = split_output(&output, &breaks, cap, opts.format);
+                for (k, part) in parts.iter().enumerate() {
+                    let part_path = part_path(path, k + 1);
+                    write_output_file(&part_path, part, cli.write_bom)?;
+                    println!("wrote {} to {}", format_size(part.len() as u64), part_path);
+                }
+                println!("split the dump into {} parts of at most {}", parts.len(), format_size(cap));
+            }
+            None => {
+                write_output_file(path, &output, cli.write_bom)?;
+                println!("wrote {} to {}", format_size(output.len() as u64), path);
+            }
+        },
+        None if !cli.clipboard && cli.pipe_to.is_none() => println!("{}", output),
+        None => {}
+    }
+
+    if cli.show_tokens {
+        eprintln!("~{} tokens", tokens::estimate_tokens(&output));
+        if cli.verbose {
+            for (path, cost) in &stats.file_tokens {
+                eprintln!("  {:>8}  {}", cost, path);
+            }
+            eprintln!("  {:>8}  (tree and scaffolding)", stats.scaffolding_tokens);
+        }
+    }
+    if cli.stats {
+        eprintln!(
+            "{} files, {} of content, {} total output",
+            stats.files,
+            format_size(stats.content_bytes),
+            format_size(output.len() as u64)
+        );
+        if opts.dedup {
+            eprintln!(
+                "--dedup pointed {} duplicate {} at their first copy, saving {}",
+                stats.duplicates,
+                if stats.duplicates == 1 { "file" } else { "files" },
+                format_size(stats.dedup_bytes)
+            );
+        }
+        let (generated, minified) = if opts.skip_generated {
+            (stats.skipped.paths(SkipReason::Generated).len(), stats.skipped.paths(SkipReason::Minified).len())
+        } else {
+            (stats.generated, stats.minified)
+        };
+        if generated + minified > 0 {
+            eprintln!(
+                "{} generated and {} minified files, {}",
+                generated,
+                minified,
+                if opts.skip_generated { "left out by --skip-generated" } else { "noted in their headers" }
+            );
+        }
+        if let Some(budget) = opts.max_tokens {
+            let strategy = match opts.pack {
+                Some(Pack::FitMost) => "fit-most",
+                Some(Pack::FitLargest) => "fit-largest",
+                None => "in order",
+            };
+            eprintln!(
+                "packed {} files ({}) into ~{} of {} tokens (~{} of them tree and scaffolding), {} dropped",
+                stats.files,
+                strategy,
+                stats.content_tokens + stats.scaffolding_tokens,
+                budget,
+                stats.scaffolding_tokens,
+                stats.skipped.paths(SkipReason::TokenBudget).len()
+            );
+        }
+        if !stats.mixed_indent.is_empty() {
+            let locations: Vec<String> =
+                stats.mixed_indent.iter().map(|(path, line)| format!("{}:{}", path, line)).collect();
+            eprintln!("mixed indentation in {} files: {}", locations.len(), locations.join(", "));
+        }
+    }
+
+    Ok(())
+}
+
+/// only an interactive terminal gets elided tree lines; anything piped or copied keeps
+/// full paths
+fn terminal_tree_width(cli: &Cli) -> Option<usize> {
+    let to_file = cli.output.as_deref().is_some_and(|path| path != "-");
+    if cli.clipboard || cli.pipe_to.is_some() || to_file || !std::io::stdout().is_terminal() {
+        return None;
+    }
+    terminal_size::terminal_size().map(|(terminal_size::Width(w), _)| w as usize)
+}
+
+/// fills in config values for every option that wasn't given on the command line
+fn apply_config(cli: &mut Cli, matches: &ArgMatches, config: Config) {
+    let from_command_line = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    if let Some(extensions) = config.extensions.filter(|_| !from_command_line("extensions")) {
+        cli.extensions = extensions.joined();
+    }
+    if let Some(exclude) = config.exclude.filter(|_| !from_command_line("exclude")) {
+        cli.exclude = exclude.joined();
+    }
+    if let Some(max_size) = config.max_size.filter(|_| !from_command_line("max_size")) {
+        cli.max_size = max_size;
+    }
+    if let Some(max_files) = config.max_files.filter(|_| !from_command_line("max_files")) {
+        cli.max_files = max_files;
+    }
+    let gitignore_flag = from_command_line("respect_gitignore") || from_command_line("no_respect_gitignore");
+    if let Some(respect) = config.respect_gitignore.filter(|_| !gitignore_flag) {
+        cli.respect_gitignore = respect;
+        cli.no_respect_gitignore = !respect;
+    }
+}
+
+/// the --extensions list. a leading `+` adds to the defaults instead of replacing them
+fn parse_extensions(list: &str) -> Vec<String> {
+    let mut extensions = Vec::new();
+    let list = match list.trim_start().strip_prefix('+') {
+        Some(extra) => {
+            extensions.extend(DEFAULT_EXTENSIONS_STR.split(',').map(normalize_extension));
+            extra
+        }
+        None => list,
+    };
+    extensions.extend(list.split(',').map(normalize_extension));
+    extensions
+}
+
+/// extensions are compared lowercased and without a leading dot, so `.rs`, `RS` and `rs`
+/// all match the same files
+fn normalize_extension(ext: &str) -> String {
+    ext.trim().trim_start_matches('.').to_lowercase()
+}
+
+fn file_extension(path: &Utf8Path) -> String {
+    normalize_extension(path.extension().unwrap_or(""))
+}
+
+/// the size limit for `rel_path`: its extension's --max-size-for if there is one, otherwise
+/// --max-size. `None` for an extension set to 0, which is left out whatever its size
+fn size_limit(opts: &DumpOptions, rel_path: &Utf8Path) -> Option<u64> {
+    match opts.max_bytes_for.get(&file_extension(rel_path)) {
+        Some(0) => None,
+        Some(&limit) => Some(limit),
+        None => Some(opts.max_bytes),
+    }
+}
+
+/// what each root's paths are shown under. with `--path-style relative` that's a name to tell
+/// the roots apart, or a single `None` for just one root, and two roots with the same name
+/// fall back to the path as given
+fn root_prefixes(directories: &[String], style: PathStyle) -> Result<Vec<Option<String>>> {
+    match style {
+        PathStyle::Relative => {}
+        PathStyle::Absolute => {
+            return directories
+                .iter()
+                .map(|d| {
+                    let path = Utf8Path::new(d).canonicalize_utf8();
+                    Ok(Some(path.with_context(|| format!("failed to resolve {}", d))?.to_string()))
+                })
+                .collect();
+        }
+        PathStyle::FromRoot => {
+            return directories.iter().map(|d| project_prefix(d, directories.len())).collect();
+        }
+    }
+    if directories.len() < 2 {
+        return Ok(vec![None; directories.len()]);
+    }
+    let labels: Vec<String> = directories.iter().map(|d| root_label(d)).collect();
+    Ok(labels
+        .iter()
+        .zip(directories)
+        .map(|(label, directory)| {
+            let unique = labels.iter().filter(|l| *l == label).count() == 1;
+            Some(if unique { label.clone() } else { directory.trim_end_matches(['/', '\\']).to_string() })
+        })
+        .collect())
+}
+
+/// `directory` relative to the nearest enclosing directory with a `.git` or `Cargo.toml`.
+/// the project root itself has nothing to show, so it keeps the usual label among several roots
+fn project_prefix(directory: &str, roots: usize) -> Result<Option<String>> {
+    let path =
+        Utf8Path::new(directory).canonicalize_utf8().with_context(|| format!("failed to resolve {}", directory))?;
+    // an archive sits in its project like any other file
+    let start = if path.is_file() { path.parent().unwrap_or(&path) } else { path.as_path() };
+    let is_project = |dir: &Utf8Path| dir.join(".git").exists() || dir.join("Cargo.toml").exists();
+    let Some(project) = start.ancestors().find(|dir| is_project(dir)) else {
+        warn!("no .git or Cargo.toml above {}, so its paths stay relative to it", directory);
+        return Ok((roots > 1).then(|| root_label(directory)));
+    };
+    debug!("paths in {} are relative to the project root {}", directory, project);
+    let rel = path.strip_prefix(project).unwrap_or(&path);
+    if rel.as_str().is_empty() {
+        return Ok((roots > 1).then(|| root_label(directory)));
+    }
+    Ok(Some(rel.to_string()))
+}
+
+fn discover_vcs_ignore(cli: &Cli, directory: &str) -> Result<Option<VcsIgnore>> {
+    // an archive's contents have nothing to do with whatever repository it sits in
+    if cli.no_vcs_ignore || source::is_archive(directory) {
+        return Ok(None);
+    }
+    VcsIgnore::discover(Path::new(directory))
+}
+
+fn changed_files(cli: &Cli, directory: &str) -> Result<Option<ChangedFiles>> {
+    if !cli.changed {
+        return Ok(None);
+    }
+    if source::is_archive(directory) {
+        anyhow::bail!("--changed needs a git repository, but {} is an archive", directory);
+    }
+    ChangedFiles::from_git(Path::new(directory), &cli.base).map(Some)
+}
+
+/// how a path below the scan root is shown in the output
+fn display_path(opts: &DumpOptions, rel_path: &Utf8Path) -> Utf8PathBuf {
+    match &opts.path_prefix {
+        Some(prefix) => Utf8Path::new(prefix).join(rel_path),
+        None => rel_path.to_owned(),
+    }
+}
+
+/// pseudonyms and --dedup's first copies (by content hash) carry over between calls, so
+/// several roots share them
+fn generate_dump(
+    directory: &str,
+    opts: &DumpOptions,
+    anonymizer: &mut Option<Anonymizer>,
+    first_copies: &mut HashMap<u64, Utf8PathBuf>,
+) -> Result<Dump> {
+    let mut output = String::new();
+    let source = source::open(directory, opts.respect_gitignore, opts.strict)?;
+    let source = source.as_ref();
+    let mut view = generate_tree_view(directory, source, opts)?;
+    output.push_str(&format::tree_section(opts.format, &view.tree));
+    for summary in &view.summaries {
+        output.push_str(&format::summary_line(opts.format, summary));
+    }
+    if opts.tree_only {
+        if opts.tree_checksum {
+            output.push_str(&format::checksum_line(opts.format, view.checksum));
+        }
+        // the tree is what a full dump would list, so that's what --min-files checks
+        let stats = DumpStats {
+            files: view.files.len(),
+            scaffolding_tokens: tokens::estimate_tokens(&output),
+            skipped: view.skipped,
+            ..Default::default()
+        };
+        let checksum = opts.tree_checksum.then_some(view.checksum);
+        let (tree, summaries) = (view.tree, view.summaries);
+        return Ok(Dump { output, breaks: Vec::new(), stats, files: Vec::new(), tree, summaries, graph: None, checksum });
+    }
+
+    let lang_overrides = LangOverrides::load(source, &view.files)?;
+    let file_set: HashSet<&Utf8Path> = view.files.iter().map(|p| p.as_path()).collect();
+    let mut dump_order: Vec<&Utf8PathBuf> = view.files.iter().collect();
+    if let Some(order) = &opts.order {
+        // listed paths are as shown in the output, so with several roots they include the root
+        let rank: HashMap<&Utf8Path, usize> =
+            order.iter().enumerate().map(|(i, path)| (path.as_path(), i)).collect();
+        let shown: HashSet<Utf8PathBuf> = view.files.iter().map(|p| display_path(opts, p)).collect();
+        let in_root = |path: &Utf8Path| opts.path_prefix.as_ref().is_none_or(|prefix| path.starts_with(prefix));
+        for path in order.iter().filter(|path| in_root(path) && !shown.contains(path.as_path())) {
+            debug!("--order-file lists {}, which is not in the dump", path);
+        }
+        // stable, so unlisted files keep the usual order after the listed ones
+        dump_order.sort_by_key(|path| {
+            rank.get(display_path(opts, path).as_path()).copied().unwrap_or(usize::MAX)
+        });
+    }
+    let process = |relative_path: &Utf8PathBuf| -> Result<Outcome> {
+        let start_time = std::time::Instant::now();
+        // a file deleted or locked since the walk shouldn't cost the rest of the dump
+        let buffer = match source.read(relative_path) {
+            Ok(buffer) => buffer,
+            Err(e) if !opts.strict => {
+                warn!("skipping {}, which couldn't be read: {:#}", relative_path, e);
+                return Ok(Outcome::Skipped(SkipReason::Unreadable));
+            }
+            Err(e) => return Err(e.context(format!("failed to read {}", relative_path))),
+        };
+        let size_bytes = buffer.len() as u64;
+        let shebang_allowed = !opts.shebang_exec_check || source.is_executable(relative_path);
+
+        // before decoding, since valid utf-8 can still be binary and decoding a big blob is wasted work
+        if !opts.include_binary && looks_binary(&buffer) {
+            debug!("binary file skipped: {}", relative_path);
+            return Ok(Outcome::Skipped(SkipReason::Binary));
+        }
+        let (content, decoded_from) = match String::from_utf8(buffer) {
+            Ok(s) => (s, None),
+            Err(e) if opts.skip_non_utf8 => {
+                debug!("non-utf8 file skipped: {} ({})", relative_path, e);
+                return Ok(Outcome::Skipped(SkipReason::NonUtf8));
+            }
+            Err(e) => {
+                let (content, encoding) = decode_non_utf8(e.as_bytes());
+                debug!("decoded {} as {}", relative_path, encoding.name());
+                (content, Some(encoding.name()))
+            }
+        };
+        // judged on the file as it is, before any transform below changes its lines
+        let generated = looks_generated(&content);
+        if let Some(generated) = generated {
+            debug!("{} looks {}", relative_path, generated.label());
+            if opts.skip_generated {
+                return Ok(Outcome::Skipped(generated.skip_reason()));
+            }
+        }
+        let content = if opts.nfc { content.nfc().collect() } else { content };
+        let content = match &opts.redactor {
+            Some(redactor) => {
+                let (content, count) = redactor.apply(relative_path, &content);
+                if count > 0 {
+                    debug!("redacted {} secrets in {}", count, relative_path);
+                }
+                content
+            }
+            None => content,
+        };
+
+        // language detection below still sees the whole file
+        let lang = {
+            let ext = file_extension(relative_path);
+            let dir_override = lang_overrides.language_for(relative_path);
+            let file_name = relative_path.file_name().unwrap_or("");
+            language_for_extension(file_name, &ext, &content, dir_override, shebang_allowed).to_string()
+        };
+        let imports = if opts.mermaid_deps {
+            let targets = imports::resolve_imports(relative_path, &content, &file_set);
+            targets.iter().map(|target| display_path(opts, target)).collect()
+        } else {
+            Vec::new()
+        };
+        let content = match opts.strip_comments.then(|| strip::strip_comments(&content, &lang)).flatten() {
+            Some(stripped) => {
+                debug!(
+                    "stripped comments from {}: {} -> {} lines",
+                    relative_path,
+                    content.lines().count(),
+                    stripped.lines().count()
+                );
+                stripped
+            }
+            None => content,
+        };
+        let content = match &opts.grep {
+            Some(pattern) => match grep::excerpt(&content, pattern, opts.context, opts.line_numbers) {
+                Some(excerpt) => excerpt,
+                None => return Ok(Outcome::Filtered),
+            },
+            None if opts.line_numbers => number_lines(&content),
+            None => content,
+        };
+        let content = match opts.truncate_lines {
+            Some(keep) if size_limit(opts, relative_path).is_some_and(|limit| size_bytes > limit) => {
+                truncate_middle(&content, keep)
+            }
+            _ => content,
+        };
+
+        debug!("processed {} in {:?}", relative_path, start_time.elapsed());
+        let file = DumpFile {
+            path: display_path(opts, relative_path),
+            language: lang,
+            size_bytes,
+            mode: if opts.show_perms { source.mode(relative_path) } else { None },
+            decoded_from,
+            modified: if opts.show_mtime { source.modified(relative_path) } else { None },
+            // an archive's files have no history to look up
+            author: if opts.show_git_info && !source::is_archive(directory) {
+                vcs::last_author(Path::new(directory), relative_path.as_std_path())
+            } else {
+                None
+            },
+            duplicate_of: None,
+            generated,
+            content,
+        };
+        // empty files are all alike, but pointing one at another saves nothing
+        let hash = (opts.dedup && !file.content.is_empty()).then(|| {
+            let mut hash = Fnv64::new();
+            hash.write(&file.content);
+            hash.finish()
+        });
+        Ok(Outcome::Dumped(Box::new(file), imports, hash))
+    };
+
+    // everything around the file blocks comes out of the same budget
+    let mut scaffolding_tokens = tokens::estimate_tokens(&output);
+    if opts.tree_checksum {
+        scaffolding_tokens += tokens::estimate_tokens(&format::checksum_line(opts.format, view.checksum));
+    }
+    let mut assembly = Assembly {
+        output,
+        breaks: Vec::new(),
+        files: Vec::new(),
+        stats: DumpStats { scaffolding_tokens, ..Default::default() },
+        output_cut: false,
+        tree_notes: HashMap::new(),
+        pending_bytes: if opts.tree_checksum {
+            format::checksum_line(opts.format, view.checksum).len() as u64
+        } else {
+            0
+        },
+    };
+    assembly.stats.skipped.absorb(std::mem::take(&mut view.skipped));
+    // packing, or a deps graph or --skip-generated tree notes that have to fit under
+    // --max-tokens or --max-output too, need every file read before anything is picked, so
+    // only then are all blocks held at once
+    let capped = opts.max_tokens.is_some() || opts.max_output.is_some();
+    let hold_blocks = (opts.max_tokens.is_some() && opts.pack.is_some())
+        || (capped && (opts.mermaid_deps || opts.skip_generated));
+    // left for file blocks when taking them in order; the first that doesn't fit ends it
+    let mut budget_left = opts.max_tokens.filter(|_| !hold_blocks).map(|budget| {
+        warn_scaffolding_over(assembly.stats.scaffolding_tokens, budget);
+        Some(budget.saturating_sub(assembly.stats.scaffolding_tokens))
+    });
+    let mut held = Vec::new();
+    let mut imports: HashMap<Utf8PathBuf, Vec<Utf8PathBuf>> = HashMap::new();
+    // the earliest file in dump order with each content, which later copies point to for
+    // now. it only goes in `first_copies` once its block is really in the output
+    let mut first_seen: HashMap<u64, Utf8PathBuf> = HashMap::new();
+
+    // files are read a window at a time and assembled in dump order as each window
+    // completes, so only about `jobs` raw files are in memory at once, not the whole tree
+    let window = rayon::current_num_threads() * 2;
+    for chunk in dump_order.chunks(window) {
+        let processed = chunk.par_iter().map(|path| process(path)).collect::<Result<Vec<_>>>()?;
+        let mut files = Vec::with_capacity(processed.len());
+        for (path, outcome) in chunk.iter().zip(processed) {
+            let (mut file, file_imports, hash) = match outcome {
+                Outcome::Dumped(file, imports, hash) => (*file, imports, hash),
+                Outcome::Skipped(reason) => {
+                    if let SkipReason::Generated | SkipReason::Minified = reason {
+                        // the note lengthens the tree, so it comes out of the budget like the tree
+                        let note = format!("[{}]", reason.label());
+                        assembly.stats.scaffolding_tokens += tree_note_cost(&note);
+                        assembly.note(path.to_path_buf(), note);
+                    }
+                    assembly.stats.skipped.push(reason, display_path(opts, path));
+                    continue;
+                }
+                Outcome::Filtered => continue,
+            };
+            // the first copy in dump order keeps its content, so which one that is stays stable.
+            // a duplicate holds on to its own until it's placed, in case that copy gets dropped
+            if let Some(hash) = hash {
+                match first_copies.get(&hash).or_else(|| first_seen.get(&hash)) {
+                    Some(first) => file.duplicate_of = Some(first.clone()),
+                    None => {
+                        first_seen.insert(hash, file.path.clone());
+                    }
+                }
+            }
+            // pseudonyms are handed out in output order, so this pass can't run in parallel
+            if let Some(anonymizer) = anonymizer.as_mut() {
+                file.content = anonymizer.apply(&file.content);
+            }
+            if opts.mermaid_deps {
+                imports.insert(file.path.clone(), file_imports);
+            }
+            files.push((path.to_path_buf(), file, hash));
+        }
+        let blocks: Vec<String> = files
+            .par_iter()
+            .map(|(_, file, _)| format::file_block(opts.format, file, opts.chunk_lines, opts.template.as_ref()))
+            .collect();
+        for ((rel_path, file, hash), block) in files.into_iter().zip(blocks) {
+            let mut placement = Placement::new(rel_path, file, block, hash);
+            if hold_blocks {
+                held.push(placement);
+                continue;
+            }
+            placement.settle_duplicate(first_copies, opts);
+            let cost = placement.cost;
+            let fits = match &mut budget_left {
+                Some(Some(left)) if cost <= *left => {
+                    *left -= cost;
+                    true
+                }
+                Some(left) => {
+                    *left = None;
+                    false
+                }
+                None => true,
+            };
+            if !fits {
+                assembly.stats.skipped.push(SkipReason::TokenBudget, placement.file.path);
+            } else if assembly.over_output_cap(&placement, opts) {
+                assembly.stats.skipped.push(SkipReason::OutputCap, placement.file.path);
+            } else {
+                assembly.push(placement, first_copies, opts);
+            }
+        }
+    }
+
+    if hold_blocks {
+        let budget = opts.max_tokens.unwrap_or(usize::MAX);
+        // the graph is reserved for as if every file made it in, which the real one can only undercut
+        if opts.mermaid_deps {
+            let files: Vec<&DumpFile> = held.iter().map(|placement| &placement.file).collect();
+            let graph = dependency_graph(&files, &imports);
+            let section = format::deps_section(opts.format, &graph);
+            assembly.stats.scaffolding_tokens += tokens::estimate_tokens(&section);
+            assembly.pending_bytes += section.len() as u64;
+        }
+        warn_scaffolding_over(assembly.stats.scaffolding_tokens, budget);
+        let costs: Vec<usize> = held.iter().map(|placement| placement.cost).collect();
+        let left = budget.saturating_sub(assembly.stats.scaffolding_tokens);
+        let selected = tokens::select_within_budget(&costs, left, opts.pack);
+        // what the picked blocks leave, for a duplicate whose first copy was dropped and
+        // now has to carry the whole content
+        let picked: usize = costs.iter().zip(&selected).filter(|(_, &keep)| keep).map(|(cost, _)| cost).sum();
+        let mut spare = left.saturating_sub(picked);
+        for (mut placement, keep) in held.into_iter().zip(selected) {
+            let packed_cost = placement.cost;
+            let fits = keep && {
+                placement.settle_duplicate(first_copies, opts);
+                placement.cost <= spare.saturating_add(packed_cost)
+            };
+            if fits {
+                spare = spare.saturating_add(packed_cost) - placement.cost;
+            }
+            if !fits {
+                assembly.stats.skipped.push(SkipReason::TokenBudget, placement.file.path);
+            } else if assembly.over_output_cap(&placement, opts) {
+                assembly.stats.skipped.push(SkipReason::OutputCap, placement.file.path);
+            } else {
+                assembly.push(placement, first_copies, opts);
+            }
+        }
+    }
+
+    let Assembly { mut output, mut breaks, files, mut stats, tree_notes, .. } = assembly;
+    let mut tree = view.tree;
+    if !tree_notes.is_empty() {
+        // the tree went out before any file was read, so its section is swapped for a noted one
+        let old_len = format::tree_section(opts.format, &tree).len();
+        tree = annotate_tree(&tree, &view.line_ends, &tree_notes);
+        let section = format::tree_section(opts.format, &tree);
+        let grown = section.len() - old_len;
+        output.replace_range(..old_len, &section);
+        for (start, _) in &mut breaks {
+            *start += grown;
+        }
+    }
+    let graph = opts.mermaid_deps.then(|| {
+        let files: Vec<&DumpFile> = files.iter().collect();
+        dependency_graph(&files, &imports)
+    });
+    if let Some(graph) = graph.as_ref().filter(|_| !hold_blocks) {
+        stats.scaffolding_tokens += tokens::estimate_tokens(&format::deps_section(opts.format, graph));
+    }
+    if let Some(graph) = &graph {
+        let section = format::deps_section(opts.format, graph);
+        output.insert_str(0, &section);
+        for (start, _) in &mut breaks {
+            *start += section.len();
+        }
+    }
+    if !stats.skipped.paths(SkipReason::TokenBudget).is_empty() {
+        let dropped: Vec<&str> = stats.skipped.paths(SkipReason::TokenBudget).iter().map(|p| p.as_str()).collect();
+        warn!(
+            "{} files dropped to stay within --max-tokens: {}",
+            dropped.len(),
+            dropped.join(", ")
+        );
+    }
+
+    if opts.tree_checksum {
+        breaks.push((output.len(), None));
+        output.push_str(&format::checksum_line(opts.format, view.checksum));
+    }
+
+    let checksum = opts.tree_checksum.then_some(view.checksum);
+    Ok(Dump { output, breaks, stats, files, tree, summaries: view.summaries, graph, checksum })
+}
+
+/// `tree` with each file's note, like `[duplicate of X]`, after its line
+fn annotate_tree(tree: &str, line_ends: &[(Utf8PathBuf, usize)], notes: &HashMap<Utf8PathBuf, String>) -> String {
+    let mut annotated = String::with_capacity(tree.len() + notes.len() * 40);
+    let mut copied = 0;
+    for (rel_path, end) in line_ends {
+        if let Some(note) = notes.get(rel_path) {
+            annotated.push_str(&tree[copied..*end]);
+            annotated.push(' ');
+            annotated.push_str(note);
+            copied = *end;
+        }
+    }
+    annotated.push_str(&tree[copied..]);
+    annotated
+}
+
+/// one relative path per line, `-` reading them from stdin
+fn read_path_list(path: &str, flag: &str) -> Result<Vec<Utf8PathBuf>> {
+    let text = if path == "-" {
+        std::io::read_to_string(std::io::stdin()).with_context(|| format!("failed to read {} from stdin", flag))?
+    } else {
+        fs::read_to_string(path).with_context(|| format!("failed to read {} {}", flag, path))?
+    };
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| Utf8PathBuf::from(line.trim_start_matches("./")))
+        .collect())
+}
+
+fn warn_scaffolding_over(scaffolding_tokens: usize, budget: usize) {
+    if scaffolding_tokens > budget {
+        warn!(
+            "the tree and other scaffolding alone take ~{} tokens, over the --max-tokens budget of {}",
+            scaffolding_tokens, budget
+        );
+    }
+}
+
+/// the mermaid graph of imports between `files`, ignoring imports of anything else
+fn dependency_graph(files: &[&DumpFile], imports: &HashMap<Utf8PathBuf, Vec<Utf8PathBuf>>) -> String {
+    let included: HashSet<&Utf8Path> = files.iter().map(|f| f.path.as_path()).collect();
+    let edges: Vec<(Utf8PathBuf, Utf8PathBuf)> = files
+        .iter()
+        .flat_map(|file| {
+            let targets = imports.get(&file.path).into_iter().flatten();
+            targets.filter(|t| included.contains(t.as_path())).map(|t| (file.path.clone(), t.clone()))
+        })
+        .collect();
+    imports::mermaid_graph(&edges)
+}
+
+/// pairs of file name templates that belong together, `{}` standing for the shared
+/// base name. the relation is symmetric
+const SIBLING_TEMPLATES: &[(&str, &str)] = &[
+    ("{}.c", "{}.h"),
+    ("{}.cc", "{}.h"),
+    ("{}.cpp", "{}.h"),
+    ("{}.cpp", "{}.hpp"),
+    ("{}.cxx", "{}.hxx"),
+    ("{}.ts", "{}.test.ts"),
+    ("{}.ts", "{}.spec.ts"),
+    ("{}.tsx", "{}.test.tsx"),
+    ("{}.js", "{}.test.js"),
+    ("{}.js", "{}.spec.js"),
+    ("{}.jsx", "{}.test.jsx"),
+    ("{}.rs", "{}_test.rs"),
+    ("{}.rs", "{}/mod.rs"),
+    ("{}.rs", "{}/tests.rs"),
+    ("{}.go", "{}_test.go"),
+    ("{}.py", "test_{}.py"),
+    ("{}.py", "{}_test.py"),
+];
+
+fn sibling_names(file_name: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    for &(a, b) in SIBLING_TEMPLATES {
+        for (from, to) in [(a, b), (b, a)] {
+            let (prefix, suffix) = from.split_once("{}").unwrap();
+            // templates with a directory only ever match from the other side
+            if from.contains('/') {
+                continue;
+            }
+            if let Some(base) = file_name.strip_prefix(prefix).and_then(|rest| rest.strip_suffix(suffix)) {
+                if !base.is_empty() {
+                    names.push(to.replace("{}", base));
+                }
+            }
+        }
+    }
+    names
+}
+
+/// pulls in files related to the kept ones (a header next to its source, a test next
+/// to its module) even when the extension filter or the file cap would have left them out
+fn bundle_siblings(
+    source: &dyn FileSource,
+    entries: &mut Vec<Entry>,
+    kept: &mut HashSet<Utf8PathBuf>,
+    opts: &DumpOptions,
+) {
+    let mut bundled: Vec<(usize, Entry)> = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        if entry.size.is_none() || !kept.contains(&entry.rel_path) {
+            continue;
+        }
+        let dir = entry.rel_path.parent().unwrap_or(Utf8Path::new(""));
+        for name in sibling_names(entry.rel_path.file_name().unwrap_or("")) {
+            let rel_path = dir.join(&name);
+            if kept.contains(&rel_path) || bundled.iter().any(|(_, e)| e.rel_path == rel_path) {
+                continue;
+            }
+            let Some(limit) = size_limit(opts, &rel_path) else {
+                continue;
+            };
+            let Some(size) = source.file_size(&rel_path).filter(|&size| size <= limit) else {
+                continue;
+            };
+            debug!("bundling {} with {}", rel_path, entry.rel_path);
+            let depth = rel_path.components().count();
+            bundled.push((index, Entry { rel_path, depth, size: Some(size) }));
+        }
+    }
+
+    // siblings already in the walk only need to be kept, the rest slot in after their partner
+    for (index, sibling) in bundled.into_iter().rev() {
+        kept.insert(sibling.rel_path.clone());
+        if !entries.iter().any(|e| e.rel_path == sibling.rel_path) {
+            entries.insert(index + 1, sibling);
+        }
+    }
+}
+
+/// orders two entries of a depth-first listing: a directory comes right before its own
+/// contents, and entries in different subtrees compare like the siblings they descend from
+fn compare_entries(a: &Entry, b: &Entry, key: SortKey) -> Ordering {
+    let (a_parts, b_parts): (Vec<&str>, Vec<&str>) = (a.rel_path.iter().collect(), b.rel_path.iter().collect());
+    let Some(i) = a_parts.iter().zip(&b_parts).position(|(x, y)| x != y) else {
+        return a_parts.len().cmp(&b_parts.len());
+    };
+
+    let (a_name, b_name) = (a_parts[i], b_parts[i]);
+    let by_name = a_name.to_lowercase().cmp(&b_name.to_lowercase()).then_with(|| a_name.cmp(b_name));
+    // the differing component is a file only if it's the last one of a file entry
+    let a_file = (i + 1 == a_parts.len()).then_some(a.size).flatten();
+    let b_file = (i + 1 == b_parts.len()).then_some(b.size).flatten();
+    match (key, a_file, b_file) {
+        (SortKey::Path, _, _) => by_name,
+        (_, None, None) => by_name,
+        (_, None, Some(_)) => Ordering::Less,
+        (_, Some(_), None) => Ordering::Greater,
+        (SortKey::Size, Some(a_size), Some(b_size)) => a_size.cmp(&b_size).then(by_name),
+        (SortKey::Ext, Some(_), Some(_)) => {
+            let ext = |name: &str| file_extension(Utf8Path::new(name));
+            ext(a_name).cmp(&ext(b_name)).then(by_name)
+        }
+    }
+}
+
+/// picks which of the matched files survive `--max-files`. ties on size fall back to
+/// the path so the selection does not depend on walk order
+fn select_capped_files(entries: &[Entry], max_files: usize, mode: MaxFilesMode) -> HashSet<Utf8PathBuf> {
+    let mut candidates: Vec<(&Utf8PathBuf, u64)> =
+        entries.iter().filter_map(|e| e.size.map(|size| (&e.rel_path, size))).collect();
+    match mode {
+        MaxFilesMode::Hard => {}
+        MaxFilesMode::SmallestFirst => candidates.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0))),
+        MaxFilesMode::LargestFirst => candidates.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0))),
+    }
+
+    candidates.into_iter().take(max_files).map(|(p, _)| p.clone()).collect()
+}
+
+/// whether a file is binary going by its first 8 kb: any nul, or more than one byte in
+/// ten being a control character that text doesn't use. utf-16 is full of nuls, so a
+/// file with a bom never counts
+fn looks_binary(bytes: &[u8]) -> bool {
+    const SNIFF_LEN: usize = 8 * 1024;
+
+    if Encoding::for_bom(bytes).is_some() {
+        return false;
+    }
+    let head = &bytes[..bytes.len().min(SNIFF_LEN)];
+    if head.contains(&0) {
+        return true;
+    }
+    // tab, newline, form feed, carriage return and the escape in ansi colour codes are all text
+    let is_control = |b: u8| (b < 0x20 && !matches!(b, b'\t' | b'\n' | 0x0c | b'\r' | 0x1b)) || b == 0x7f;
+    let control = head.iter().filter(|&&b| is_control(b)).count();
+    control * 10 > head.len()
+}
+
+/// whether a file looks minified, going by an average line over 400 characters, or
+/// generated, going by a marker like `@generated` or `DO NOT EDIT` in its first lines
+fn looks_generated(content: &str) -> Option<Generated> {
+    const MINIFIED_LINE_LEN: usize = 400;
+    const MARKER_LINES: usize = 5;
+    const MARKERS: &[&str] = &["@generated", "do not edit", "code generated by"];
+
+    let head = content.lines().take(MARKER_LINES).collect::<Vec<_>>().join("\n").to_lowercase();
+    if MARKERS.iter().any(|marker| head.contains(marker)) {
+        return Some(Generated::Marked);
+    }
+    let lines = content.lines().count();
+    (lines > 0 && content.chars().count() / lines > MINIFIED_LINE_LEN).then_some(Generated::Minified)
+}
+
+/// decodes a file that isn't valid utf-8: by its bom if it has one, otherwise by
+/// whatever chardetng guesses
+fn decode_non_utf8(bytes: &[u8]) -> (String, &'static Encoding) {
+    let encoding = match Encoding::for_bom(bytes) {
+        Some((encoding, _)) => encoding,
+        None => {
+            let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+            detector.feed(bytes, true);
+            match detector.guess(None, Utf8Detection::Deny) {
+                encoding if encoding == UTF_8 => WINDOWS_1252,
+                encoding => encoding,
+            }
+        }
+    };
+    // decode strips the bom and replaces anything unmappable
+    let (content, encoding, _) = encoding.decode(bytes);
+    (content.into_owned(), encoding)
+}
+
+/// (path, detected encoding, valid utf-8) for each file
+fn encoding_rows(
+    source: &dyn FileSource,
+    files: &[Utf8PathBuf],
+    opts: &DumpOptions,
+) -> Result<Vec<(String, &'static str, bool)>> {
+    files
+        .par_iter()
+        .map(|relative_path| {
+            let buffer = source.read(relative_path).with_context(|| format!("failed to read {}", relative_path))?;
+            let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+            detector.feed(&buffer, true);
+            let encoding = detector.guess(None, Utf8Detection::Allow);
+            let path = display_path(opts, relative_path).to_string();
+            Ok((path, encoding.name(), std::str::from_utf8(&buffer).is_ok()))
+        })
+        .collect()
+}
+
+fn encoding_report(rows: Vec<(String, &'static str, bool)>) -> String {
+    let path_width = rows.iter().map(|(p, _, _)| p.len()).max().unwrap_or(0).max("path".len());
+    let mut report = format!("{:<path_width$}  {:<14}  utf-8\n", "path", "encoding");
+    for (path, encoding, valid_utf8) in rows {
+        report.push_str(&format!(
+            "{:<path_width$}  {:<14}  {}\n",
+            path,
+            encoding,
+            if valid_utf8 { "yes" } else { "no" }
+        ));
+    }
+
+    report
+}
+
+/// the name shown at the top of the tree. paths like `.`, `..` or `src/..` have no file
+/// name of their own, so fall back to the directory they resolve to
+fn root_label(path: &str) -> String {
+    let path = Utf8Path::new(path);
+    if let Some(name) = path.file_name() {
+        return name.to_string();
+    }
+    path.canonicalize_utf8()
+        .ok()
+        .and_then(|p| p.file_name().map(str::to_string))
+        .unwrap_or_else(|| path.to_string())
+}
+
+fn generate_tree_view(path: &str, source: &dyn FileSource, opts: &DumpOptions) -> Result<TreeView> {
+    let mut file_count = 0;
+    let mut tree = String::new();
+    let mut files = Vec::new();
+
+    // only the displayed label changes, the walk still uses the real path
+    let base = match (&opts.root_alias, &opts.path_prefix) {
+        (Some(alias), _) => alias.clone(),
+        // the header is written the same way as the paths below it
+        (None, Some(prefix)) if opts.path_style != PathStyle::Relative => prefix.clone(),
+        _ => root_label(path),
+    };
+    tree.push_str(&format!("{}/\n", base));
+
+    let mut entries = Vec::new();
+    let mut depth_cut: HashSet<Utf8PathBuf> = HashSet::new();
+    // directories left in the tree for --show-excluded, which the walk doesn't go into
+    let mut collapsed: HashSet<Utf8PathBuf> = HashSet::new();
+    let mut skipped = SkipReport::default();
+    if let Some(listed) = &opts.files_from {
+        entries = listed_entries(source, listed, opts, &mut skipped);
+    } else {
+        let mut dump_ignore = DumpIgnore::default();
+        dump_ignore.load_dir(source, Utf8Path::new(""))?;
+        source.walk(&mut |entry| {
+            let name = entry.rel_path.file_name().unwrap_or("");
+            let is_dir = entry.size.is_none();
+            let rel_path = entry.rel_path.as_std_path();
+            // globs are written with forward slashes whatever the platform
+            let glob_path = slash_path(&entry.rel_path);
+            // what the user asked to leave out, as opposed to hidden, ignored or unchanged paths
+            let excluded = opts.exclude_dirs.iter().any(|d| name == d.as_str())
+                || opts.exclude_globs.is_match(&glob_path)
+                || (is_dir && opts.exclude_globs.is_match(format!("{}/", glob_path)))
+                || dump_ignore.is_ignored(&entry.rel_path, is_dir);
+            if excluded {
+                // hidden and unchanged directories stay out of sight all the same
+                let shown = is_dir
+                    && opts.show_excluded
+                    && opts.max_depth.is_none_or(|limit| entry.depth <= limit)
+                    && opts.hidden.allows(rel_path, is_dir)
+                    && opts.changed.as_ref().is_none_or(|c| c.contains_dir(rel_path));
+                if shown {
+                    collapsed.insert(entry.rel_path.clone());
+                    entries.push(entry.clone());
+                }
+                return Ok(Visit::Skip);
+            }
+            if !opts.hidden.allows(rel_path, is_dir)
+                || opts.vcs_ignore.as_ref().is_some_and(|v| v.is_ignored(rel_path))
+                || opts.changed.as_ref().is_some_and(|c| {
+                    if is_dir { !c.contains_dir(rel_path) } else { !c.contains_file(rel_path) }
+                })
+                || opts.selection.as_ref().is_some_and(|s| {
+                    let shown = display_path(opts, &entry.rel_path);
+                    if is_dir { !s.contains_dir(&shown) } else { !s.contains_file(&shown) }
+                })
+            {
+                return Ok(Visit::Skip);
+            }
+            // entries one level past the limit are only looked at to tell whether anything was cut
+            if opts.max_depth.is_some_and(|limit| entry.depth > limit + 1) {
+                let ext = file_extension(&entry.rel_path);
+                if is_dir || opts.extensions.contains(&ext) {
+                    depth_cut.insert(entry.rel_path.parent().unwrap_or(Utf8Path::new("")).to_owned());
+                }
+                return Ok(Visit::Skip);
+            }
+            match entry.size {
+                Some(size_bytes) => {
+                    let ext = file_extension(&entry.rel_path);
+                    let included = opts.include_globs.is_empty() || opts.include_globs.is_match(&glob_path);
+                    if !opts.extensions.contains(&ext) || !included {
+                        return Ok(Visit::Continue);
+                    }
+                    // an extension with a --max-size-for of 0 is left out as if it never matched
+                    let Some(limit) = size_limit(opts, &entry.rel_path) else {
+                        return Ok(Visit::Continue);
+                    };
+                    if size_bytes > limit && opts.truncate_lines.is_none() {
+                        skipped.push(SkipReason::TooLarge, entry.rel_path.clone());
+                        return Ok(Visit::Continue);
+                    }
+                    // in hard mode the walk order decides, so there is no point walking past the cap
+                    if opts.max_files_mode == MaxFilesMode::Hard && file_count >= opts.max_files {
+                        skipped.scan_cut = true;
+                        return Ok(Visit::Stop);
+                    }
+                    file_count += 1;
+                    entries.push(entry.clone());
+                }
+                None => {
+                    dump_ignore.load_dir(source, &entry.rel_path)?;
+                    entries.push(entry.clone());
+                }
+            }
+            Ok(Visit::Continue)
+        })?;
+    }
+
+    let mut kept = select_capped_files(&entries, opts.max_files, opts.max_files_mode);
+    if opts.bundle_siblings {
+        bundle_siblings(source, &mut entries, &mut kept, opts);
+    }
+    entries.sort_by(|a, b| compare_entries(a, b, opts.sort));
+
+    // only the outermost matching directory is summarized, nested matches fold into it
+    let mut summaries: Vec<DirSummary> = Vec::new();
+    for entry in entries.iter().filter(|e| e.size.is_none() && !collapsed.contains(&e.rel_path)) {
+        let dir = entry.rel_path.as_str();
+        let matches = opts.summarize_dirs.is_match(dir) || opts.summarize_dirs.is_match(format!("{}/", dir));
+        if matches && !summaries.iter().any(|s| entry.rel_path.starts_with(&s.path)) {
+            summaries.push(DirSummary {
+                path: entry.rel_path.clone(),
+                files: 0,
+                bytes: 0,
+                languages: BTreeSet::new(),
+            });
+        }
+    }
+
+    for entry in entries.iter().filter(|e| e.size.is_some() && !kept.contains(&e.rel_path)) {
+        skipped.push(SkipReason::MaxFiles, entry.rel_path.clone());
+    }
+    // only what gets drawn counts when working out which entry is the last of its siblings
+    entries.retain(|e| e.size.is_none() || kept.contains(&e.rel_path));
+
+    // bottom-up rollup: every kept file counts towards each directory above it
+    let mut dir_counts: HashMap<&Utf8Path, usize> = HashMap::new();
+    if opts.dir_counts {
+        for entry in entries.iter().filter(|e| e.size.is_some()) {
+            for dir in entry.rel_path.ancestors().skip(1) {
+                *dir_counts.entry(dir).or_default() += 1;
+            }
+        }
+    }
+
+    let last_sibling = last_siblings(&entries);
+
+    // sizes of the files in one directory line up in a column, except behind names so long
+    // that padding their siblings out to them would make the tree harder to read
+    let mut columns: HashMap<&Utf8Path, (usize, usize)> = HashMap::new();
+    for entry in &entries {
+        let Some(size_bytes) = entry.size else {
+            continue;
+        };
+        let path_len = display_path(opts, &entry.rel_path).as_str().chars().count();
+        let column = columns.entry(entry.rel_path.parent().unwrap_or(Utf8Path::new(""))).or_default();
+        if path_len <= MAX_ALIGNED_PATH {
+            column.0 = column.0.max(path_len);
+        }
+        column.1 = column.1.max(format_size(size_bytes).len());
+    }
+
+    let mut checksum = Fnv64::new();
+    let mut line_ends = Vec::new();
+    // whether each ancestor of the current entry was the last of its own siblings
+    let mut ancestors_last: Vec<bool> = Vec::new();
+    for (entry, &is_last) in entries.iter().zip(&last_sibling) {
+        ancestors_last.truncate(entry.depth - 1);
+        let mut lead: String =
+            ancestors_last.iter().map(|&last| if last { "    " } else { "│   " }).collect();
+        lead.push_str(if is_last { "└── " } else { "├── " });
+        ancestors_last.push(is_last);
+        match entry.size {
+            Some(size_bytes) => {
+                let truncated = opts.truncate_lines.is_some()
+                    && size_limit(opts, &entry.rel_path).is_some_and(|limit| size_bytes > limit);
+                let (path_column, size_column) = columns[entry.rel_path.parent().unwrap_or(Utf8Path::new(""))];
+                let annotation = format!(
+                    " [{:>width$}]{}",
+                    format_size(size_bytes),
+                    if truncated { " [truncated]" } else { "" },
+                    width = size_column
+                );
+                let shown = display_path(opts, &entry.rel_path);
+                push_tree_line(&mut tree, opts.tree_width, &lead, shown.as_str(), path_column, &annotation);
+                line_ends.push((entry.rel_path.clone(), tree.len() - 1));
+                checksum.write(entry.rel_path.as_str());
+                checksum.write("\n");
+                match summaries.iter_mut().find(|s| entry.rel_path.starts_with(&s.path)) {
+                    Some(summary) => {
+                        summary.files += 1;
+                        summary.bytes += size_bytes;
+                        let ext = file_extension(&entry.rel_path);
+                        let by_name = language_for_file_name(entry.rel_path.file_name().unwrap_or(""));
+                        if let Some(lang) = by_name.or_else(|| LANG_MAP.get(ext.as_str()).copied()) {
+                            summary.languages.insert(lang);
+                        }
+                    }
+                    None => files.push(entry.rel_path.clone()),
+                }
+            }
+            None => {
+                let dir = format!("{}/", display_path(opts, &entry.rel_path));
+                let annotation = if collapsed.contains(&entry.rel_path) {
+                    " [excluded]".to_string()
+                } else if opts.dir_counts {
+                    let count = dir_counts.get(entry.rel_path.as_path()).copied().unwrap_or(0);
+                    format!(" ({} {})", count, if count == 1 { "file" } else { "files" })
+                } else {
+                    String::new()
+                };
+                push_tree_line(&mut tree, opts.tree_width, &lead, &dir, 0, &annotation);
+                checksum.write(entry.rel_path.as_str());
+                checksum.write("/\n");
+                if depth_cut.contains(&entry.rel_path) {
+                    let mut note_lead: String =
+                        ancestors_last.iter().map(|&last| if last { "    " } else { "│   " }).collect();
+                    note_lead.push_str("└── ");
+                    tree.push_str(&format!("{}... (depth limit reached)\n", note_lead));
+                }
+            }
+        }
+    }
+
+    for summary in &mut summaries {
+        summary.path = display_path(opts, &summary.path);
+    }
+    let skipped = skipped.map_paths(|path| display_path(opts, path));
+    Ok(TreeView { tree, files, summaries, checksum: checksum.finish(), skipped, line_ends })
+}
+
+/// the files named by --files-from plus every directory above them, in walk order.
+/// missing files and files over the size limit are skipped with a warning
+fn listed_entries(
+    source: &dyn FileSource,
+    listed: &[Utf8PathBuf],
+    opts: &DumpOptions,
+    skipped: &mut SkipReport,
+) -> Vec<Entry> {
+    let mut dirs = BTreeSet::new();
+    let mut files = HashMap::new();
+    for rel_path in listed {
+        if opts.selection.as_ref().is_some_and(|s| !s.contains_file(&display_path(opts, rel_path))) {
+            continue;
+        }
+        let Some(limit) = size_limit(opts, rel_path) else {
+            debug!("--files-from lists {}, whose extension --max-size-for leaves out", rel_path);
+            continue;
+        };
+        match source.file_size(rel_path) {
+            None => warn!("--files-from lists {}, which is not a file; skipping it", rel_path),
+            Some(size) if size > limit && opts.truncate_lines.is_none() => {
+                warn!("--files-from lists {}, which is over the size limit; skipping it", rel_path);
+                skipped.push(SkipReason::TooLarge, rel_path.clone());
+            }
+            Some(size) => {
+                let ancestors = rel_path.ancestors().skip(1).filter(|dir| !dir.as_str().is_empty());
+                dirs.extend(ancestors.map(Utf8Path::to_owned));
+                files.insert(rel_path.clone(), size);
+            }
+        }
+    }
+    let mut entries: Vec<Entry> = dirs
+        .into_iter()
+        .map(|rel_path| Entry { depth: rel_path.components().count(), rel_path, size: None })
+        .chain(files.into_iter().map(|(rel_path, size)| Entry {
+            depth: rel_path.components().count(),
+            rel_path,
+            size: Some(size),
+        }))
+        .collect();
+    entries.sort_by(|a, b| a.rel_path.components().cmp(b.rel_path.components()));
+    entries
+}
+
+/// width of the line number column for a file with `lines` lines
+fn line_number_width(lines: usize) -> usize {
+    lines.max(1).to_string().len()
+}
+
+/// `  12 | fn main() {`, keeping the line's own ending
+fn numbered_line(width: usize, number: usize, line: &str) -> String {
+    format!("{:>width$} | {}", number, line)
+}
+
+/// keeps the first and last `keep` lines with a marker saying how many were cut between
+fn truncate_middle(content: &str, keep: usize) -> String {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    if lines.len() <= keep * 2 {
+        return content.to_string();
+    }
+    let mut truncated: String = lines[..keep].concat();
+    truncated.push_str(&format!("... [truncated {} lines] ...\n", lines.len() - keep * 2));
+    truncated.push_str(&lines[lines.len() - keep..].concat());
+    truncated
+}
+
+fn number_lines(content: &str) -> String {
+    let width = line_number_width(content.split_inclusive('\n').count());
+    content.split_inclusive('\n').enumerate().map(|(i, line)| numbered_line(width, i + 1, line)).collect()
+}
+
+/// for each entry of a depth-first listing, whether no later sibling follows it
+fn last_siblings(entries: &[Entry]) -> Vec<bool> {
+    let mut last = vec![false; entries.len()];
+    // walking backwards, `seen[d]` says a sibling at depth d has already turned up
+    let mut seen: Vec<bool> = Vec::new();
+    for (i, entry) in entries.iter().enumerate().rev() {
+        seen.resize(entry.depth, false);
+        last[i] = !seen[entry.depth - 1];
+        seen[entry.depth - 1] = true;
+    }
+    last
+}
+
+fn slash_path(path: &Utf8Path) -> String {
+    path.iter().collect::<Vec<_>>().join("/")
+}
+
+fn build_globset(patterns: &[String], flag: &str) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).with_context(|| format!("invalid {} glob: {}", flag, pattern))?);
+    }
+    Ok(builder.build()?)
+}
+
+/// appends one tree line, eliding the middle of `path` when the whole line would not
+/// fit in `width` columns. the indent/branch lead and the annotation are never cut, and
+/// `pad_to` pads the path with spaces so annotations line up, as long as the padded line
+/// still fits in `width`
+fn push_tree_line(
+    tree: &mut String,
+    width: Option<usize>,
+    lead: &str,
+    path: &str,
+    pad_to: usize,
+    annotation: &str,
+) {
+    const ELLIPSIS: &str = "...";
+
+    let fixed = lead.chars().count() + annotation.chars().count();
+    let path_len = path.chars().count();
+    let path = match width {
+        Some(width) if fixed + path_len > width && width > fixed + ELLIPSIS.len() + 1 => {
+            // keep more of the tail since that's where the file name is
+            let keep = width - fixed - ELLIPSIS.len();
+            let head = keep / 3;
+            let tail = keep - head;
+            let start: String = path.chars().take(head).collect();
+            let end: String = path.chars().skip(path_len - tail).collect();
+            format!("{}{}{}", start, ELLIPSIS, end)
+        }
+        _ => path.to_string(),
+    };
+    let path_len = path.chars().count();
+    let padding = if width.is_some_and(|width| fixed + pad_to > width) { 0 } else { pad_to.saturating_sub(path_len) };
+
+    tree.push_str(lead);
+    tree.push_str(&path);
+    tree.push_str(&" ".repeat(padding));
+    tree.push_str(annotation);
+    tree.push('\n');
+}
+
+/// fnv-1a, used where a hash has to be stable across runs, platforms and rust releases
+struct Fnv64(u64);
+
+impl Fnv64 {
+    fn new() -> Self {
+        Fnv64(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, s: &str) {
+        for &b in s.as_bytes() {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// the first line whose leading whitespace disagrees with the file's first indented
+/// line, or mixes tabs and spaces itself. space alignment after a tab is allowed
+fn first_mixed_indent(content: &str) -> Option<usize> {
+    let mut style = None;
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+        let Some(first) = indent.chars().next() else {
+            continue;
+        };
+        let mixed_within = first == ' ' && indent.contains('\t');
+        if mixed_within || *style.get_or_insert(first) != first {
+            return Some(number + 1);
+        }
+    }
+    None
+}
+
+fn format_size(bytes: u64) -> String {
+    const KIB: u64 = 1024;
+    const MIB: u64 = 1024 * KIB;
+
+    if bytes < KIB {
+        format!("{} B", bytes)
+    } else if bytes < MIB {
+        format!("{:.1} KiB", bytes as f64 / KIB as f64)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / MIB as f64)
+    }
+}
+
+fn language_for_extension<'a>(
+    file_name: &str,
+    ext: &str,
+    content: &str,
+    dir_override: Option<&'a str>,
+    shebang_allowed: bool,
+) -> &'a str {
+    if let Some(lang) = dir_override {
+        return lang;
+    }
+    if let Some(lang) = language_for_file_name(file_name) {
+        return lang;
+    }
+    LANG_MAP.get(ext).copied().unwrap_or_else(|| {
+        if content.starts_with("#!") && shebang_allowed {
+            detect_shebang(content)
+        } else if ext.is_empty() {
+            match detect_special_file(content) {
+                "" => sniff_content(content),
+                lang => lang,
+            }
+        } else {
+            ""
+        }
+    })
+}
+
+fn detect_shebang(content: &str) -> &'static str {
+    lazy_static::lazy_static! {
+        static ref SHEBANG_RE: Regex = Regex::new(r"^#!\s*/usr/bin/env\s+(\w+)|^#!\s*/.*/(\w+)").unwrap();
+    }
+
+    if let Some(first_line) = content.lines().next() {
+        if let Some(caps) = SHEBANG_RE.captures(first_line) {
+            let lang = caps.get(1).or_else(|| caps.get(2)).map(|m| m.as_str());
+            return match lang {
+                Some("python3") | Some("python") => "python",
+                Some("ruby") => "ruby",
+                Some("node") | Some("nodejs") => "javascript",
+                Some("bash") | Some("sh") => "bash",
+                Some("perl") => "perl",
+                Some("php") => "php",
+                Some("lua") => "lua",
+                Some("Rscript") => "r",
+                _ => "",
+            };
+        }
+    }
+    ""
+}
+
+/// `Dockerfile`, `Makefile` and friends, including suffixed ones like `Dockerfile.dev`
+fn language_for_file_name(file_name: &str) -> Option<&'static str> {
+    let name = file_name.to_lowercase();
+    if let Some(lang) = FILENAME_MAP.get(name.as_str()) {
+        return Some(lang);
+    }
+    let (stem, _) = name.split_once('.')?;
+    matches!(stem, "dockerfile" | "containerfile" | "makefile").then(|| FILENAME_MAP[stem])
+}
+
+fn detect_special_file(content: &str) -> &'static str {
+    if content.contains("FROM ") {
+        "dockerfile"
+    } else if content.contains("JAVA_HOME") {
+        "properties"
+    } else {
+        ""
+    }
+}
+
+fn sniff_content(content: &str) -> &'static str {
+    lazy_static::lazy_static! {
+        static ref TOML_LINE_RE: Regex =
+            Regex::new(r#"^\s*(\[\[?[\w.\-"]+\]\]?|[\w.\-"]+\s*=\s*\S.*)\s*$"#).unwrap();
+    }
+
+    let trimmed = content.trim_start();
+    if trimmed.starts_with("<?xml") {
+        return "xml";
+    }
+    if (trimmed.starts_with('{') || trimmed.starts_with('[')) && looks_like_json(trimmed) {
+        return "json";
+    }
+    if content.lines().next().is_some_and(|l| l.trim_end() == "---") {
+        return "yaml";
+    }
+
+    // toml is only a guess when every one of the first few meaningful lines fits the shape
+    let sample: Vec<&str> = content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .take(8)
+        .collect();
+    if !sample.is_empty() && sample.iter().all(|l| TOML_LINE_RE.is_match(l)) {
+        return "toml";
+    }
+
+    ""
+}
+
+fn looks_like_json(content: &str) -> bool {
+    const SAMPLE_LEN: usize = 4096;
+
+    let mut end = content.len().min(SAMPLE_LEN);
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    // a truncated sample fails with an eof error, which still means the prefix was valid
+    match serde_json::from_str::<serde_json::Value>(&content[..end]) {
+        Ok(_) => true,
+        Err(e) => e.is_eof(),
+    }
+}
+
+/// command-line clipboard tools tried in order when arboard has no display to talk to
+const CLIPBOARD_COMMANDS: &[(&str, &[&str])] = &[
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+    ("xsel", &["--clipboard", "--input"]),
+];
+
+fn set_clipboard(text: &str) -> Result<()> {
+    // failing to connect at all (no display, headless session) won't fix itself, so skip
+    // straight to the fallbacks; only setting the text is worth retrying
+    let mut clipboard = match Clipboard::new() {
+        Ok(clipboard) => clipboard,
+        Err(e) => {
+            debug!("no system clipboard ({}), trying clipboard commands", e);
+            return set_clipboard_with_command(text);
+        }
+    };
+
+    let mut attempts = 0;
     let max_attempts = 3;
 
     while attempts < max_attempts {
-        match Clipboard::new().and_then(|mut c| c.set_text(text)) {
+        match clipboard.set_text(text) {
             Ok(_) => return Ok(()),
             Err(e) if attempts == max_attempts - 1 => return Err(e.into()),
             Err(_) => {
@@ -348,3 +2735,524 @@ fn set_clipboard(text: &str) -> Result<()> {
 
     Ok(())
 }
+
+fn set_clipboard_with_command(text: &str) -> Result<()> {
+    for &(program, args) in CLIPBOARD_COMMANDS {
+        let mut child = match Command::new(program).args(args).stdin(Stdio::piped()).spawn() {
+            Ok(child) => child,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("failed to run {}", program)),
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes()).with_context(|| format!("failed to write to {}", program))?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            anyhow::bail!("{} exited with {}", program, status);
+        }
+        debug!("copied output with {}", program);
+        return Ok(());
+    }
+
+    anyhow::bail!(
+        "no clipboard available and none of wl-copy, xclip or xsel is installed; use --output to write a file instead"
+    )
+}
+
+/// `dump.md` becomes `dump.part2.md`
+fn part_path(path: &str, k: usize) -> String {
+    let path = Utf8Path::new(path);
+    let name = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => format!("{}.part{}.{}", stem, k, ext),
+        _ => format!("{}.part{}", path.file_name().unwrap_or("dump"), k),
+    };
+    path.with_file_name(name).into_string()
+}
+
+/// whether `name` is one of the file names `part_path` gives the parts of `output`
+fn is_part_name(name: &str, output: &str) -> bool {
+    let output = Utf8Path::new(output);
+    let (prefix, suffix) = match (output.file_stem(), output.extension()) {
+        (Some(stem), Some(ext)) => (format!("{}.part", stem), format!(".{}", ext)),
+        _ => (format!("{}.part", output.file_name().unwrap_or("dump")), String::new()),
+    };
+    let number = name.strip_prefix(&prefix).and_then(|rest| rest.strip_suffix(&suffix));
+    number.is_some_and(|k| !k.is_empty() && k.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// cuts the dump into parts of at most `cap` bytes, each starting with a `part K of N`
+/// header. cuts only fall on `breaks`, so a file's block always stays in one part; a
+/// block bigger than the cap gets a part to itself
+fn split_output(
+    output: &str,
+    breaks: &[(usize, Option<Utf8PathBuf>)],
+    cap: u64,
+    format: Format,
+) -> Vec<String> {
+    let mut pieces = Vec::with_capacity(breaks.len() + 1);
+    let mut starts =
+        std::iter::once((0, None)).chain(breaks.iter().map(|(start, path)| (*start, path.as_ref())));
+    let mut current = starts.next();
+    while let Some((start, path)) = current {
+        current = starts.next();
+        let end = current.map_or(output.len(), |(next, _)| next);
+        if end > start {
+            pieces.push((&output[start..end], path));
+        }
+    }
+
+    // room for the longest header this many parts could need
+    let header_len = format::part_header(format, pieces.len(), pieces.len()).len() as u64;
+    let mut parts: Vec<Vec<&str>> = Vec::new();
+    let mut part_len = 0;
+    for (piece, path) in pieces {
+        let len = piece.len() as u64;
+        if len + header_len > cap {
+            let what = path.map_or("the tree".to_string(), |path| path.to_string());
+            warn!("{} alone is bigger than --split {}, so its part is too", what, format_size(cap));
+        }
+        if parts.is_empty() || part_len + len + header_len > cap {
+            parts.push(Vec::new());
+            part_len = 0;
+        }
+        parts.last_mut().unwrap().push(piece);
+        part_len += len;
+    }
+
+    let total = parts.len();
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(k, pieces)| format::part_header(format, k + 1, total) + &pieces.concat())
+        .collect()
+}
+
+/// `80kb`, `2mb` or plain bytes, in the same 1024-based units the sizes are shown in
+fn parse_size(text: &str) -> Result<u64, String> {
+    let lower = text.trim().to_lowercase();
+    let digits = lower.find(|c: char| !c.is_ascii_digit()).unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(digits);
+    let number: u64 = number.parse().map_err(|_| format!("expected a size like 80kb, got `{}`", text))?;
+    let multiplier = match unit.trim() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        unit => return Err(format!("unknown size unit `{}` (use b, kb or mb)", unit)),
+    };
+    match number.checked_mul(multiplier) {
+        Some(0) => Err("the size has to be more than zero".to_string()),
+        Some(size) => Ok(size),
+        None => Err(format!("size too large: `{}`", text)),
+    }
+}
+
+/// an `ext=kb` pair for --max-size-for, with the size in bytes
+fn parse_size_for(text: &str) -> Result<(String, u64), String> {
+    let Some((ext, kb)) = text.split_once('=') else {
+        return Err(format!("expected ext=kb like json=20, got `{}`", text));
+    };
+    let ext = normalize_extension(ext);
+    if ext.is_empty() {
+        return Err(format!("no extension before the `=` in `{}`", text));
+    }
+    let kb: u64 = kb.trim().parse().map_err(|_| format!("expected a size in kb after the `=`, got `{}`", kb))?;
+    match kb.checked_mul(1024) {
+        Some(size) => Ok((ext, size)),
+        None => Err(format!("size too large: `{}` kb", kb)),
+    }
+}
+
+fn write_output_file(path: &str, text: &str, bom: bool) -> Result<()> {
+    if let Some(parent) = Path::new(path).parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    // written in place rather than copied behind a bom, since the dump can be large
+    let mut file = fs::File::create(path).with_context(|| format!("failed to write {}", path))?;
+    // some windows tools only recognize utf-8 with a bom; it never goes to stdout or the clipboard
+    if bom {
+        file.write_all(b"\xEF\xBB\xBF").with_context(|| format!("failed to write {}", path))?;
+    }
+    file.write_all(text.as_bytes()).with_context(|| format!("failed to write {}", path))
+}
+
+fn pipe_to_command(cmd: &str, text: &str) -> Result<()> {
+    let mut child = if cfg!(windows) {
+        Command::new("cmd").args(["/C", cmd]).stdin(Stdio::piped()).spawn()?
+    } else {
+        Command::new("sh").args(["-c", cmd]).stdin(Stdio::piped()).spawn()?
+    };
+
+    // take stdin so it is closed before waiting, otherwise the child never sees eof.
+    // a broken pipe just means the command stopped reading; its exit status tells the story
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(text.as_bytes()) {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        anyhow::bail!("command exited with {}", status);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn format_size_switches_units_at_each_power_of_1024() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(1048576), "1.0 MiB");
+    }
+
+    #[test]
+    fn extensionless_files_are_recognised_by_content() {
+        assert_eq!(sniff_content("<?xml version=\"1.0\"?>\n<config/>\n"), "xml");
+        assert_eq!(sniff_content("  {\"name\": \"demo\", \"tags\": [1, 2]}\n"), "json");
+        assert_eq!(sniff_content("[1, 2, 3]"), "json");
+        assert_eq!(sniff_content("---\nname: demo\n"), "yaml");
+        assert_eq!(sniff_content("# settings\n[server]\nport = 8080\nhost = \"::\"\n"), "toml");
+        // brackets alone aren't json, and prose isn't toml
+        assert_eq!(sniff_content("[server]\nport = 8080\n"), "toml");
+        assert_eq!(sniff_content("{ not json at all"), "");
+        assert_eq!(sniff_content("just some notes\nabout the project\n"), "");
+    }
+
+    #[test]
+    fn content_sniffing_only_runs_when_extension_and_shebang_say_nothing() {
+        let json = "{\"a\": 1}";
+        assert_eq!(language_for_extension("data", "", json, None, true), "json");
+        assert_eq!(language_for_extension("data.txt", "txt", json, None, true), "text");
+        assert_eq!(language_for_extension("data.unknown", "unknown", json, None, true), "");
+        assert_eq!(language_for_extension("run", "", "#!/usr/bin/env python3\n{}", None, true), "python");
+        assert_eq!(language_for_extension("run", "", "<?xml?>", Some("html"), true), "html");
+    }
+
+    #[test]
+    fn allow_hidden_reincludes_only_matching_dotfiles() {
+        let filter = HiddenFilter::new(false, &[".github/**".to_string(), ".cargo/config.toml".to_string()]).unwrap();
+        assert!(filter.allows(Path::new("src/main.rs"), false));
+        assert!(filter.allows(Path::new(".github/workflows/ci.yml"), false));
+        assert!(filter.allows(Path::new(".cargo/config.toml"), false));
+        assert!(!filter.allows(Path::new(".cargo/credentials.toml"), false));
+        assert!(!filter.allows(Path::new(".env"), false));
+        assert!(!filter.allows(Path::new("src/.secret.rs"), false));
+        // directories on the way to an allowed file are walked, others aren't
+        assert!(filter.allows(Path::new(".github"), true));
+        assert!(filter.allows(Path::new(".cargo"), true));
+        assert!(!filter.allows(Path::new(".vscode"), true));
+
+        let everything = HiddenFilter::new(true, &[]).unwrap();
+        assert!(everything.allows(Path::new(".env"), false));
+    }
+
+    #[test]
+    fn allow_hidden_reaches_files_in_a_dump() {
+        let root = TempDir::new();
+        root.write("src/main.rs", "fn main() {}\n");
+        root.write(".github/workflows/ci.yml", "on: push\n");
+        root.write(".cargo/config.toml", "[build]\n");
+        root.write(".vscode/settings.toml", "x = 1\n");
+        let output = dump_with(&root, &["--allow-hidden", ".github/**", "--allow-hidden", ".cargo/**"]);
+        assert!(output.contains("# file: .github/workflows/ci.yml"));
+        assert!(output.contains("# file: .cargo/config.toml"));
+        assert!(output.contains("# file: src/main.rs"));
+        assert!(!output.contains(".vscode"));
+    }
+
+    #[test]
+    fn each_max_files_mode_keeps_its_own_files() {
+        let entry = |rel_path: &str, size: Option<u64>| Entry {
+            rel_path: rel_path.into(),
+            depth: Utf8Path::new(rel_path).components().count() - 1,
+            size,
+        };
+        // walk order, with a directory in the way and a tie on size between b.rs and d.rs
+        let entries = [
+            entry("src", None),
+            entry("src/c.rs", Some(300)),
+            entry("src/a.rs", Some(10)),
+            entry("src/d.rs", Some(50)),
+            entry("src/b.rs", Some(50)),
+        ];
+        let kept = |mode| {
+            let mut kept: Vec<_> = select_capped_files(&entries, 2, mode).into_iter().collect();
+            kept.sort();
+            kept
+        };
+
+        assert_eq!(kept(MaxFilesMode::Hard), ["src/a.rs", "src/c.rs"]);
+        assert_eq!(kept(MaxFilesMode::LargestFirst), ["src/b.rs", "src/c.rs"]);
+        assert_eq!(kept(MaxFilesMode::SmallestFirst), ["src/a.rs", "src/b.rs"]);
+        assert_eq!(select_capped_files(&entries, 10, MaxFilesMode::SmallestFirst).len(), 4);
+    }
+
+    #[test]
+    fn summarize_dirs_glob_only_summarizes_the_directories_it_matches() {
+        let root = TempDir::new();
+        root.write("vendor/lib/a.rs", "pub fn a() {}\n");
+        root.write("vendor/b.py", "b = 1\n");
+        root.write("src/vendor/c.rs", "pub fn c() {}\n");
+        root.write("src/main.rs", "fn main() {}\n");
+        // vendor is in the default excludes, so replace them to let it through at all
+        let output = dump_with(&root, &["-x", "target", "--summarize-dirs-glob", "vendor/**"]);
+        assert!(output.contains("# directory: vendor/ (summarized: 2 files, "), "{}", output);
+        assert!(!output.contains("pub fn a()"));
+        assert!(!output.contains("# file: vendor/"));
+        // the glob is anchored at the scan root, so a nested vendor is dumped as usual
+        assert!(output.contains("# file: src/vendor/c.rs"));
+        assert!(output.contains("# file: src/main.rs"));
+        assert!(!output.contains("# directory: src/vendor/"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shebang_exec_check_only_trusts_executable_scripts() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new();
+        let script = "#!/usr/bin/env python3\nprint(1)\n";
+        let run = root.write("run.xyz", script);
+        root.write("notes.xyz", script);
+        fs::set_permissions(&run, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let checked = dump_with(&root, &["-e", "xyz", "--shebang-exec-check"]);
+        assert!(checked.contains("# file: run.xyz\n\n```python\n"), "{}", checked);
+        assert!(checked.contains("# file: notes.xyz\n\n```\n"), "{}", checked);
+        // without the check a shebang counts either way
+        let unchecked = dump_with(&root, &["-e", "xyz"]);
+        assert!(unchecked.contains("# file: notes.xyz\n\n```python\n"), "{}", unchecked);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn show_perms_marks_executable_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new();
+        let script = root.write("run.sh", "#!/bin/sh\necho hi\n");
+        let lib = root.write("lib.sh", "greet() { echo hi; }\n");
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(&lib, fs::Permissions::from_mode(0o640)).unwrap();
+
+        let output = dump_with(&root, &["--show-perms"]);
+        assert!(output.contains("# file: run.sh (mode 0755, executable)\n"), "{}", output);
+        assert!(output.contains("# file: lib.sh (mode 0640)\n"), "{}", output);
+        assert!(!dump_with(&root, &[]).contains("(mode "));
+    }
+
+    #[test]
+    fn parse_size_rejects_sizes_that_overflow() {
+        assert_eq!(parse_size("80kb"), Ok(80 * 1024));
+        assert_eq!(parse_size("2M"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_size(&u64::MAX.to_string()), Ok(u64::MAX));
+        let too_large = format!("{}kb", u64::MAX / 1024 + 1);
+        assert_eq!(parse_size(&too_large), Err(format!("size too large: `{}`", too_large)));
+        assert!(parse_size("0kb").is_err());
+    }
+
+    #[test]
+    fn parse_size_for_rejects_sizes_that_overflow() {
+        assert_eq!(parse_size_for(".json=20"), Ok(("json".to_string(), 20 * 1024)));
+        assert_eq!(parse_size_for("lock=0"), Ok(("lock".to_string(), 0)));
+        let too_large = format!("json={}", u64::MAX / 1024 + 1);
+        assert_eq!(parse_size_for(&too_large), Err(format!("size too large: `{}` kb", u64::MAX / 1024 + 1)));
+    }
+
+    #[test]
+    fn json_size_bytes_is_the_size_on_disk() {
+        let root = TempDir::new();
+        let source = "// says hello\nfn main() {\n    let api_key = \"sk-abcdefghijklmnopqrstuvwx\";\n}\n";
+        root.write("main.rs", source);
+        let output = dump_with(&root, &["--format", "json", "--strip-comments", "--line-numbers", "--redact"]);
+        let document: serde_json::Value = serde_json::from_str(&output).unwrap();
+        let file = &document["files"][0];
+        assert_ne!(file["content"].as_str().unwrap(), source, "the transforms should change the content");
+        assert_eq!(file["size_bytes"].as_u64(), Some(source.len() as u64));
+    }
+
+    #[test]
+    fn write_bom_prefixes_the_file_and_nothing_else() {
+        let out = TempDir::new();
+        let path = out.path().join("dump.md");
+        let text = "# file: a.rs\n\u{e9}t\u{e9}\n";
+        write_output_file(path.as_str(), text, true).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(Encoding::for_bom(&bytes), Some((UTF_8, 3)));
+        assert_eq!(std::str::from_utf8(&bytes[3..]).unwrap(), text);
+
+        write_output_file(path.as_str(), text, false).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), text.as_bytes());
+    }
+
+    #[test]
+    fn write_bom_leaves_the_dump_itself_unchanged() {
+        let root = TempDir::new();
+        root.write("a.rs", "fn a() {}\n");
+        let plain = dump_with(&root, &[]);
+        let with_bom = dump_with(&root, &["--write-bom"]);
+        assert_eq!(with_bom.strip_prefix('\u{feff}'), Some(plain.as_str()));
+    }
+
+    /// dumps `root` with `args` on top of `-o` into a scratch file, returning what was written
+    fn dump_with(root: &TempDir, args: &[&str]) -> String {
+        dump_dirs(&[root.path().as_str()], args)
+    }
+
+    fn dump_dirs(directories: &[&str], args: &[&str]) -> String {
+        let out = TempDir::new();
+        let output = out.path().join("dump.md");
+        let mut argv = vec!["dumpcode"];
+        argv.extend(directories);
+        argv.extend(["--no-config", "-o", output.as_str()]);
+        argv.extend(args);
+        let cli = Cli::parse_from(argv);
+        let mut opts = dump_options(&cli).unwrap();
+        let prefixes = root_prefixes(&cli.directories, cli.path_style).unwrap();
+        dump_and_deliver(&cli, &mut opts, &prefixes).unwrap();
+        fs::read_to_string(output).unwrap()
+    }
+
+    #[test]
+    fn absolute_roots_give_paths_relative_to_themselves() {
+        let root = TempDir::new();
+        root.write("src/api/handler.rs", "pub fn handle() {}\n");
+        root.write("main.rs", "fn main() {}\n");
+        let name = root.path().file_name().unwrap();
+        let nested = root.path().join("src/api");
+        assert!(root.path().is_absolute());
+
+        // spelled with a trailing slash and a detour, neither of which may leak into the paths
+        let detour = format!("{}/src/../", root.path());
+        for directory in [root.path().as_str(), &format!("{}/", root.path()), &detour] {
+            let output = dump_dirs(&[directory], &[]);
+            assert!(output.contains("# file: src/api/handler.rs\n"), "{}", output);
+            assert!(output.contains("# file: main.rs\n"));
+            assert!(!output.contains(root.path().as_str()), "{}", output);
+        }
+        assert!(dump_dirs(&[root.path().as_str()], &[]).contains(&format!("\n{}/\n", name)));
+
+        let output = dump_dirs(&[nested.as_str()], &[]);
+        assert!(output.contains("\napi/\n└── handler.rs"), "{}", output);
+        assert!(output.contains("# file: handler.rs\n"));
+
+        let output = dump_dirs(&[nested.as_str()], &["--path-style", "absolute"]);
+        assert!(output.contains(&format!("# file: {}/handler.rs\n", nested.canonicalize_utf8().unwrap())));
+    }
+
+    #[test]
+    fn dedup_never_points_at_a_copy_the_budget_dropped() {
+        let root = TempDir::new();
+        let content = "fn shared() {\n    println!(\"the same body in three files\");\n}\n".repeat(8);
+        for name in ["a.rs", "b.rs", "c.rs"] {
+            root.write(name, &content);
+        }
+        // fit-most packs the small pointers first, so some budgets drop a.rs, the first copy
+        // in path order, while keeping the files that duplicate it
+        let total = tokens::estimate_tokens(&dump_with(&root, &["--dedup"]));
+        let mut dropped_first = false;
+        for budget in 1..=total {
+            let budget = budget.to_string();
+            let output = dump_with(&root, &["--dedup", "--max-tokens", &budget, "--pack", "fit-most"]);
+            let block_pointers = output.lines().filter(|line| line.starts_with("[duplicate of "));
+            for pointer in block_pointers {
+                let first = pointer.trim_start_matches("[duplicate of ").trim_end_matches(']');
+                assert!(output.contains(&format!("# file: {}\n\n```rust\n", first)), "{}", output);
+            }
+            let dumped = output.matches("\n# file: ").count();
+            assert!(dumped == 0 || output.matches("the same body").count() == 8, "{}", output);
+            dropped_first |= dumped > 0 && !output.contains("# file: a.rs");
+        }
+        assert!(dropped_first);
+    }
+
+    #[test]
+    fn max_output_leaves_room_for_the_graph_checksum_and_tree_notes() {
+        let root = TempDir::new();
+        root.write("src/main.rs", "mod a;\nmod b;\n\nfn main() {\n    a::run();\n}\n");
+        root.write("src/a.rs", "use crate::b;\n\npub fn run() {\n    b::helper();\n}\n");
+        root.write("src/b.rs", "pub fn helper() {\n    println!(\"helping\");\n}\n");
+        root.write("src/c.rs", "pub fn helper() {\n    println!(\"helping\");\n}\n");
+        let flags = ["--mermaid-deps", "--tree-checksum", "--dedup"];
+        let total = dump_with(&root, &flags).len();
+        let mut cut_between_files = false;
+        for cap in (total / 2..total + 64).step_by(3) {
+            let cap_arg = format!("{}b", cap);
+            let mut args = flags.to_vec();
+            args.extend(["--max-output", &cap_arg]);
+            let output = dump_with(&root, &args);
+            assert!(output.contains("```mermaid") && output.contains("# tree checksum: "));
+            // only the tree and the sections around it can't be left out
+            let files = output.matches("# file: ").count();
+            assert!(files == 0 || output.len() <= cap, "{} bytes over a cap of {}:\n{}", output.len(), cap, output);
+            cut_between_files |= files > 0 && output.contains("due to --max-output");
+        }
+        assert!(cut_between_files);
+    }
+
+    #[test]
+    fn part_names_match_every_part_of_their_output() {
+        for k in [1, 2, 10, 123] {
+            let part = part_path("dumps/dump.md", k);
+            assert!(is_part_name(Utf8Path::new(&part).file_name().unwrap(), "dumps/dump.md"), "{}", part);
+        }
+        assert!(is_part_name("dump.part4", "dump"));
+        assert!(!is_part_name("dump.md", "dump.md"));
+        assert!(!is_part_name("dump.part.md", "dump.md"));
+        assert!(!is_part_name("dump.partx.md", "dump.md"));
+        assert!(!is_part_name("other.part1.md", "dump.md"));
+        assert!(!is_part_name("dump.part1.txt", "dump.md"));
+    }
+
+    #[test]
+    fn watch_ignores_the_split_parts_it_wrote() {
+        let dir = TempDir::new();
+        let source = dir.write("src/lib.rs", "pub fn f() {}\n");
+        let part = dir.write("dumps/dump.part3.md", "# part 3 of 3\n");
+        let stranger = dir.write("dumps/notes.md", "notes\n");
+        let output = dir.path().join("dumps/dump.md");
+        let cli = Cli::parse_from(["dumpcode", dir.path().as_str(), "--watch", "--split", "1kb", "-o", output.as_str()]);
+
+        let is_own_output = own_outputs(&cli);
+        let canonical = |path: &Utf8Path| path.as_std_path().canonicalize().unwrap();
+        assert!(is_own_output(&canonical(&part)));
+        assert!(!is_own_output(&canonical(&stranger)));
+        assert!(!is_own_output(&canonical(&source)));
+    }
+
+    #[test]
+    fn max_tokens_holds_for_the_whole_dump() {
+        let root = TempDir::new();
+        root.write("src/main.rs", "mod a;\nmod b;\n\nfn main() {\n    a::run();\n}\n");
+        root.write("src/a.rs", "use crate::b;\n\npub fn run() {\n    b::helper();\n}\n");
+        root.write("src/b.rs", "pub fn helper() {\n    println!(\"helping out\");\n}\n");
+        root.write("src/copy_of_b.rs", "pub fn helper() {\n    println!(\"helping out\");\n}\n");
+        root.write("src/bundle.min.js", "var x=1;".repeat(100));
+        let flags = ["--dedup", "--tree-checksum", "--skip-generated", "-e", "+js"];
+        let total = tokens::estimate_tokens(&dump_with(&root, &flags));
+        let mut partial = false;
+        for extra in [&[][..], &["--pack", "fit-most"], &["--mermaid-deps"]] {
+            for budget in total / 2..total + 10 {
+                let budget_arg = budget.to_string();
+                let mut args = flags.to_vec();
+                args.extend(extra);
+                args.extend(["--max-tokens", &budget_arg]);
+                let output = dump_with(&root, &args);
+                let files = output.matches("# file: ").count();
+                let used = tokens::estimate_tokens(&output);
+                // the tree and the sections around it always go in, whatever they cost
+                assert!(files == 0 || used <= budget, "~{} tokens over a budget of {}:\n{}", used, budget, output);
+                partial |= files > 0 && files < 4;
+            }
+        }
+        assert!(partial);
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::{cursor, execute, queue, terminal};
+use std::collections::HashSet;
+use std::io::{IsTerminal, Write};
+
+use crate::format_size;
+use crate::tokens::estimate_tokens_from_size;
+
+/// a file offered in the picker, by the path shown in the output
+pub struct Candidate {
+    pub path: Utf8PathBuf,
+    pub size: u64,
+}
+
+/// the files picked with `--select`, by the path shown in the output
+#[derive(Debug)]
+pub struct Selection {
+    files: HashSet<Utf8PathBuf>,
+    // every directory above a picked file, so the walk can skip the rest
+    dirs: HashSet<Utf8PathBuf>,
+}
+
+impl Selection {
+    fn new(files: HashSet<Utf8PathBuf>) -> Self {
+        let dirs = files.iter().flat_map(|path| path.ancestors().skip(1)).map(Utf8Path::to_owned).collect();
+        Selection { files, dirs }
+    }
+
+    pub fn contains_file(&self, path: &Utf8Path) -> bool {
+        self.files.contains(path)
+    }
+
+    pub fn contains_dir(&self, path: &Utf8Path) -> bool {
+        self.dirs.contains(path)
+    }
+}
+
+/// whether there's a terminal to draw the picker on and read keys from
+pub fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+/// lets the user pick from `candidates`, all checked to begin with. space toggles a file,
+/// `a` toggles them all, enter confirms and esc, `q` or ctrl-c cancel
+pub fn pick(candidates: &[Candidate]) -> Result<Selection> {
+    if candidates.is_empty() {
+        anyhow::bail!("--select has no files to choose from");
+    }
+    let mut stdout = std::io::stdout();
+    terminal::enable_raw_mode().context("failed to set up the terminal for --select")?;
+    let entered = execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide);
+    let result = entered.map_err(anyhow::Error::from).and_then(|()| run(&mut stdout, candidates));
+    // put the terminal back even if drawing failed
+    let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+
+    let Some(checked) = result? else {
+        anyhow::bail!("--select was cancelled, nothing dumped");
+    };
+    let files: HashSet<Utf8PathBuf> =
+        candidates.iter().zip(&checked).filter(|(_, &on)| on).map(|(c, _)| c.path.clone()).collect();
+    if files.is_empty() {
+        anyhow::bail!("no files selected, nothing dumped");
+    }
+    Ok(Selection::new(files))
+}
+
+struct Picker<'a> {
+    candidates: &'a [Candidate],
+    checked: Vec<bool>,
+    cursor: usize,
+    // index of the first candidate on screen
+    scroll: usize,
+    path_width: usize,
+}
+
+/// the picker loop: which candidates are checked, or `None` if cancelled
+fn run(out: &mut impl Write, candidates: &[Candidate]) -> Result<Option<Vec<bool>>> {
+    let mut picker = Picker {
+        candidates,
+        checked: vec![true; candidates.len()],
+        cursor: 0,
+        scroll: 0,
+        path_width: candidates.iter().map(|c| c.path.as_str().chars().count()).max().unwrap_or(0),
+    };
+    let last = candidates.len() - 1;
+    loop {
+        let (width, height) = terminal::size()?;
+        // one line of help above the list and one of totals below it
+        let rows = (height as usize).saturating_sub(2).max(1);
+        if picker.cursor < picker.scroll {
+            picker.scroll = picker.cursor;
+        } else if picker.cursor >= picker.scroll + rows {
+            picker.scroll = picker.cursor + 1 - rows;
+        }
+        picker.draw(out, rows, width as usize)?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        let cursor = picker.cursor;
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+            KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
+            KeyCode::Enter => return Ok(Some(picker.checked)),
+            KeyCode::Char(' ') => picker.checked[cursor] = !picker.checked[cursor],
+            KeyCode::Char('a') => {
+                let all = picker.checked.iter().all(|&on| on);
+                picker.checked.iter_mut().for_each(|on| *on = !all);
+            }
+            KeyCode::Up | KeyCode::Char('k') => picker.cursor = cursor.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => picker.cursor = (cursor + 1).min(last),
+            KeyCode::PageUp => picker.cursor = cursor.saturating_sub(rows),
+            KeyCode::PageDown => picker.cursor = (cursor + rows).min(last),
+            KeyCode::Home => picker.cursor = 0,
+            KeyCode::End => picker.cursor = last,
+            _ => {}
+        }
+    }
+}
+
+impl Picker<'_> {
+    fn draw(&self, out: &mut impl Write, rows: usize, width: usize) -> Result<()> {
+        queue!(out, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0))?;
+        let help = "space toggles, a toggles all, enter dumps the checked files, esc cancels";
+        queue!(out, Print(fit(help, width)), Print("\r\n"))?;
+        for (i, candidate) in self.candidates.iter().enumerate().skip(self.scroll).take(rows) {
+            let line = format!(
+                "[{}] {:<path_width$}  {:>10}",
+                if self.checked[i] { 'x' } else { ' ' },
+                candidate.path,
+                format_size(candidate.size),
+                path_width = self.path_width
+            );
+            if i == self.cursor {
+                queue!(out, SetAttribute(Attribute::Reverse), Print(fit(&line, width)))?;
+                queue!(out, SetAttribute(Attribute::Reset))?;
+            } else {
+                queue!(out, Print(fit(&line, width)))?;
+            }
+            queue!(out, Print("\r\n"))?;
+        }
+
+        let picked: Vec<&Candidate> =
+            self.candidates.iter().zip(&self.checked).filter(|(_, &on)| on).map(|(c, _)| c).collect();
+        let bytes: u64 = picked.iter().map(|c| c.size).sum();
+        let tokens: usize = picked.iter().map(|c| estimate_tokens_from_size(c.size)).sum();
+        let totals = format!(
+            "{} of {} files, {} of content, ~{} tokens",
+            picked.len(),
+            self.candidates.len(),
+            format_size(bytes),
+            tokens
+        );
+        let footer_row = (rows + 1).min(u16::MAX as usize) as u16;
+        queue!(out, cursor::MoveTo(0, footer_row), SetAttribute(Attribute::Bold), Print(fit(&totals, width)))?;
+        queue!(out, SetAttribute(Attribute::Reset))?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// `line` cut to the terminal width, so nothing wraps and pushes the list around
+fn fit(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
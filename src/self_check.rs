@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use log::debug;
+use std::collections::BTreeMap;
+
+use crate::format::Format;
+use crate::DumpFile;
+
+/// parses the dump back and compares it against the files that went into it, so
+/// format bugs (like a fence closing early) surface as a hard error
+pub fn verify(output: &str, files: &[DumpFile], format: Format) -> Result<()> {
+    let parsed = match format {
+        Format::Markdown => parse_markdown(output),
+        Format::Rag => parse_rag(output),
+        Format::Json => parse_json(output)?,
+    };
+    // a --dedup duplicate has no content of its own to compare
+    let expected: BTreeMap<&str, &str> = files
+        .iter()
+        .filter(|f| f.duplicate_of.is_none())
+        .map(|f| (f.path.as_str(), f.content.as_str()))
+        .collect();
+
+    let mut problems = Vec::new();
+    for (path, content) in &expected {
+        match parsed.get(*path) {
+            None => problems.push(format!("{}: missing from parsed output", path)),
+            Some(parsed_content) if parsed_content != content => {
+                let difference = first_difference(content, parsed_content);
+                problems.push(format!("{}: content differs {}", path, difference))
+            }
+            Some(_) => {}
+        }
+    }
+    for path in parsed.keys() {
+        if !expected.contains_key(path.as_str()) {
+            problems.push(format!("{}: parsed but never written", path));
+        }
+    }
+
+    if !problems.is_empty() {
+        anyhow::bail!("self-check failed:\n  {}", problems.join("\n  "));
+    }
+    debug!("self-check passed for {} files", expected.len());
+    Ok(())
+}
+
+/// reads `# file:` blocks. a block's content runs from the line after the opening
+/// fence up to the first line that is exactly the same fence
+fn parse_markdown(output: &str) -> BTreeMap<String, String> {
+    let mut files = BTreeMap::new();
+    let mut lines = output.split('\n');
+
+    while let Some(line) = lines.next() {
+        let Some(path) = line.strip_prefix("# file: ") else {
+            continue;
+        };
+        // drop the --show-git-info, --show-mtime and --show-perms notes, then a
+        // --chunk-large-files part number
+        let path = strip_note(path, " (likely ");
+        let path = strip_note(path, " (last commit by ");
+        let path = strip_note(path, " (modified ");
+        let path = strip_note(path, " (mode ");
+        let (path, is_part) = match path.rsplit_once(" (part ") {
+            Some((path, note)) if note.ends_with(')') => (path, true),
+            _ => (path, false),
+        };
+        if lines.next() != Some("") {
+            continue;
+        }
+        let Some(mut open) = lines.next() else {
+            break;
+        };
+        if open.starts_with("<!-- decoded as ") {
+            let Some(line) = lines.next() else {
+                break;
+            };
+            open = line;
+        }
+        let fence = &open[..open.len() - open.trim_start_matches('`').len()];
+        if fence.len() < 3 {
+            continue;
+        }
+
+        let mut body = Vec::new();
+        for line in lines.by_ref() {
+            if line == fence {
+                break;
+            }
+            body.push(line);
+        }
+        insert_part(&mut files, path, body.join("\n"), is_part);
+    }
+
+    files
+}
+
+/// reads `<!-- BEGIN path lang=x -->` blocks up to the matching `<!-- END path -->`
+fn parse_rag(output: &str) -> BTreeMap<String, String> {
+    let mut files = BTreeMap::new();
+    let mut lines = output.split('\n');
+
+    while let Some(line) = lines.next() {
+        let Some(header) = line.strip_prefix("<!-- BEGIN ").and_then(|l| l.strip_suffix(" -->")) else {
+            continue;
+        };
+        let Some((path, attributes)) = header.rsplit_once(" lang=") else {
+            continue;
+        };
+        let is_part = attributes.split(' ').any(|a| a.starts_with("part="));
+
+        let end = format!("<!-- END {} -->", path);
+        let mut body = Vec::new();
+        for line in lines.by_ref() {
+            if line == end {
+                break;
+            }
+            body.push(line);
+        }
+        insert_part(&mut files, path, body.join("\n"), is_part);
+    }
+
+    files
+}
+
+/// `header` without a trailing ` (note ...)` that starts with `opener`
+fn strip_note<'a>(header: &'a str, opener: &str) -> &'a str {
+    match header.rsplit_once(opener) {
+        Some((header, note)) if note.ends_with(')') => header,
+        _ => header,
+    }
+}
+
+// parts of a chunked file follow each other in order, so they just concatenate
+fn insert_part(files: &mut BTreeMap<String, String>, path: &str, body: String, is_part: bool) {
+    if is_part {
+        files.entry(path.to_string()).or_default().push_str(&body);
+    } else {
+        files.insert(path.to_string(), body);
+    }
+}
+
+/// reads `path` and `content` from each element of the `files` array
+fn parse_json(output: &str) -> Result<BTreeMap<String, String>> {
+    let document: serde_json::Value = serde_json::from_str(output).context("output is not valid json")?;
+    let mut files = BTreeMap::new();
+    for file in document["files"].as_array().context("output has no `files` array")? {
+        if file.get("duplicate_of").is_some() {
+            continue;
+        }
+        let (Some(path), Some(content)) = (file["path"].as_str(), file["content"].as_str()) else {
+            anyhow::bail!("a `files` element lacks a string `path` or `content`");
+        };
+        files.insert(path.to_string(), content.to_string());
+    }
+    Ok(files)
+}
+
+fn first_difference(expected: &str, actual: &str) -> String {
+    let line = expected
+        .split('\n')
+        .zip(actual.split('\n'))
+        .position(|(a, b)| a != b)
+        .unwrap_or_else(|| expected.split('\n').count().min(actual.split('\n').count()));
+    format!(
+        "at line {} ({} vs {} lines)",
+        line + 1,
+        expected.split('\n').count(),
+        actual.split('\n').count()
+    )
+}
@@ -0,0 +1,97 @@
+use camino::Utf8PathBuf;
+use std::collections::BTreeMap;
+
+/// why a file that passed the extension and exclude filters still isn't in the dump
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipReason {
+    TooLarge,
+    MaxFiles,
+    NonUtf8,
+    Binary,
+    Unreadable,
+    TokenBudget,
+    OutputCap,
+    Generated,
+    Minified,
+}
+
+impl SkipReason {
+    pub fn label(self) -> &'static str {
+        match self {
+            SkipReason::TooLarge => "over the size limit",
+            SkipReason::MaxFiles => "past --max-files",
+            SkipReason::NonUtf8 => "not utf-8",
+            SkipReason::Binary => "binary",
+            SkipReason::Unreadable => "unreadable",
+            SkipReason::TokenBudget => "over the --max-tokens budget",
+            SkipReason::OutputCap => "past --max-output",
+            SkipReason::Generated => "generated",
+            SkipReason::Minified => "minified",
+        }
+    }
+}
+
+/// every file left out of the dump, by reason, so an incomplete dump never looks complete
+#[derive(Debug, Default)]
+pub struct SkipReport {
+    paths: BTreeMap<SkipReason, Vec<Utf8PathBuf>>,
+    // a hard --max-files cap ends the walk, so how many files lay beyond it is unknown
+    pub scan_cut: bool,
+}
+
+impl SkipReport {
+    pub fn push(&mut self, reason: SkipReason, path: Utf8PathBuf) {
+        self.paths.entry(reason).or_default().push(path);
+    }
+
+    pub fn paths(&self, reason: SkipReason) -> &[Utf8PathBuf] {
+        self.paths.get(&reason).map_or(&[], Vec::as_slice)
+    }
+
+    /// the same report with every path rewritten, e.g. into how it's shown in the output
+    pub fn map_paths(self, f: impl Fn(&Utf8PathBuf) -> Utf8PathBuf) -> SkipReport {
+        let paths =
+            self.paths.into_iter().map(|(reason, paths)| (reason, paths.iter().map(&f).collect())).collect();
+        SkipReport { paths, scan_cut: self.scan_cut }
+    }
+
+    pub fn absorb(&mut self, other: SkipReport) {
+        for (reason, paths) in other.paths {
+            self.paths.entry(reason).or_default().extend(paths);
+        }
+        self.scan_cut |= other.scan_cut;
+    }
+
+    /// whether --max-files left anything out
+    pub fn max_files_hit(&self) -> bool {
+        self.scan_cut || !self.paths(SkipReason::MaxFiles).is_empty()
+    }
+
+    /// one line like `skipped 3 files: 2 over the size limit, 1 binary`, or `None` if
+    /// nothing was skipped
+    pub fn summary(&self, max_files: usize) -> Option<String> {
+        let total: usize = self.paths.values().map(Vec::len).sum();
+        if total == 0 && !self.scan_cut {
+            return None;
+        }
+        let mut parts = Vec::new();
+        if total > 0 {
+            let counts: Vec<String> =
+                self.paths.iter().map(|(reason, paths)| format!("{} {}", paths.len(), reason.label())).collect();
+            let files = if total == 1 { "file" } else { "files" };
+            parts.push(format!("skipped {} {}: {}", total, files, counts.join(", ")));
+        }
+        if self.scan_cut {
+            parts.push(format!("stopped scanning at --max-files {}, so later files weren't looked at", max_files));
+        }
+        Some(parts.join("; "))
+    }
+
+    /// every skipped path with its reason, one per line
+    pub fn details(&self) -> String {
+        self.paths
+            .iter()
+            .flat_map(|(reason, paths)| paths.iter().map(move |path| format!("  {} ({})\n", path, reason.label())))
+            .collect()
+    }
+}
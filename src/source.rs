@@ -0,0 +1,481 @@
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use flate2::read::GzDecoder;
+use log::{debug, warn};
+use ignore::WalkBuilder;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use walkdir::WalkDir;
+
+/// one file or directory below the scan root
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub rel_path: Utf8PathBuf,
+    pub depth: usize,
+    // none for directories
+    pub size: Option<u64>,
+}
+
+/// what the walk should do after visiting an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    Continue,
+    /// don't descend into this directory
+    Skip,
+    Stop,
+}
+
+/// where the files being dumped come from. everything past the walk (filters, reads,
+/// language detection) only talks to this, so a directory and an archive behave the same
+pub trait FileSource: Sync {
+    /// visits entries depth-first with parents before their children
+    fn walk(&self, visit: &mut dyn FnMut(&Entry) -> Result<Visit>) -> Result<()>;
+
+    fn read(&self, rel_path: &Utf8Path) -> Result<Vec<u8>>;
+
+    /// size of a regular file, or `None` if there's no such file
+    fn file_size(&self, rel_path: &Utf8Path) -> Option<u64>;
+
+    fn is_executable(&self, rel_path: &Utf8Path) -> bool;
+
+    /// permission bits of a file, or `None` where the platform has none
+    fn mode(&self, rel_path: &Utf8Path) -> Option<u32>;
+
+    /// when a file was last modified, if known
+    fn modified(&self, rel_path: &Utf8Path) -> Option<SystemTime>;
+}
+
+pub fn is_archive(path: &str) -> bool {
+    let lower = path.to_lowercase();
+    [".tar", ".tar.gz", ".tgz"].iter().any(|ext| lower.ends_with(ext)) && Utf8Path::new(path).is_file()
+}
+
+/// the source for a scan root, an archive or a directory. `respect_gitignore` only applies
+/// to directories; archives are taken as they are. with `strict`, a file or directory the
+/// walk can't read ends it with an error instead of being warned about and left out
+pub fn open(path: &str, respect_gitignore: bool, strict: bool) -> Result<Box<dyn FileSource>> {
+    if is_archive(path) {
+        Ok(Box::new(TarSource::open(Utf8Path::new(path))?))
+    } else {
+        Ok(Box::new(DirSource { root: Utf8PathBuf::from(path), respect_gitignore, strict }))
+    }
+}
+
+pub struct DirSource {
+    root: Utf8PathBuf,
+    respect_gitignore: bool,
+    strict: bool,
+}
+
+impl DirSource {
+    /// the value, or `None` after a warning for an error a non-strict walk gets past
+    fn tolerate<T, E>(&self, result: std::result::Result<T, E>) -> Result<Option<T>>
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if self.strict => Err(e.into()),
+            Err(e) => {
+                warn!("skipping what couldn't be read: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// like the plain walk, but leaves out whatever `.gitignore`, `.ignore`, `.git/info/exclude`
+    /// and the global git excludes ignore, nested files included. dotfiles are left to the
+    /// caller's own hidden filter
+    fn walk_ignoring(&self, visit: &mut dyn FnMut(&Entry) -> Result<Visit>) -> Result<()> {
+        // the walker reads a directory's children only once it moves past the directory,
+        // so a directory marked here gets its children filtered out before any are visited
+        let skipped: Arc<Mutex<HashSet<PathBuf>>> = Arc::default();
+        let filter_skipped = Arc::clone(&skipped);
+        let walker = WalkBuilder::new(&self.root)
+            .hidden(false)
+            .parents(true)
+            .require_git(false)
+            .follow_links(false)
+            .same_file_system(true)
+            .sort_by_file_name(name_order)
+            .filter_entry(move |entry| {
+                let skipped = filter_skipped.lock().unwrap();
+                !entry.path().parent().is_some_and(|parent| skipped.contains(parent))
+            })
+            .build();
+
+        for entry in walker {
+            let Some(entry) = self.tolerate(entry)? else {
+                continue;
+            };
+            if entry.depth() == 0 {
+                continue;
+            }
+            if entry.path_is_symlink() {
+                warn!("skipping symlink: {}", entry.path().display());
+                continue;
+            }
+
+            let entry_path = entry.path();
+            let Some(rel_path) = self.rel_path(entry_path)? else {
+                continue;
+            };
+            let size = match entry.file_type() {
+                Some(kind) if kind.is_file() => match self.tolerate(entry.metadata())? {
+                    Some(metadata) => Some(metadata.len()),
+                    None => continue,
+                },
+                Some(kind) if kind.is_dir() => None,
+                _ => continue,
+            };
+
+            match visit(&Entry { rel_path, depth: entry.depth(), size })? {
+                Visit::Continue => {}
+                Visit::Skip if size.is_none() => {
+                    skipped.lock().unwrap().insert(entry_path.to_path_buf());
+                }
+                Visit::Skip => {}
+                Visit::Stop => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `None` (after a warning) for paths that aren't utf-8
+    fn rel_path(&self, entry_path: &Path) -> Result<Option<Utf8PathBuf>> {
+        // walkers join every entry onto the root they were given, so stripping that exact
+        // root always works, whether it was relative, absolute or had a trailing slash
+        let rel_path = entry_path.strip_prefix(self.root.as_std_path()).with_context(|| {
+            format!("{} is not below the scan root {}", entry_path.display(), self.root)
+        })?;
+        let rel_path = Utf8Path::from_path(rel_path).map(Utf8Path::to_owned);
+        if rel_path.is_none() {
+            warn!("skipping non-utf8 path: {}", entry_path.display());
+        }
+        Ok(rel_path)
+    }
+}
+
+impl FileSource for DirSource {
+    fn walk(&self, visit: &mut dyn FnMut(&Entry) -> Result<Visit>) -> Result<()> {
+        if self.respect_gitignore {
+            return self.walk_ignoring(visit);
+        }
+
+        let mut walker = WalkDir::new(&self.root)
+            .min_depth(1)
+            .follow_links(false)
+            .same_file_system(true)
+            .sort_by(|a, b| name_order(a.file_name(), b.file_name()))
+            .into_iter();
+
+        while let Some(entry) = walker.next() {
+            let Some(entry) = self.tolerate(entry)? else {
+                continue;
+            };
+            if entry.path_is_symlink() {
+                warn!("skipping symlink: {}", entry.path().display());
+                continue;
+            }
+
+            let Some(rel_path) = self.rel_path(entry.path())? else {
+                continue;
+            };
+            let size = if entry.file_type().is_file() {
+                match self.tolerate(entry.metadata())? {
+                    Some(metadata) => Some(metadata.len()),
+                    None => continue,
+                }
+            } else if entry.file_type().is_dir() {
+                None
+            } else {
+                continue;
+            };
+
+            match visit(&Entry { rel_path, depth: entry.depth(), size })? {
+                Visit::Continue => {}
+                Visit::Skip if size.is_none() => walker.skip_current_dir(),
+                Visit::Skip => {}
+                Visit::Stop => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read(&self, rel_path: &Utf8Path) -> Result<Vec<u8>> {
+        let mut file = fs::File::open(self.root.join(rel_path))?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn file_size(&self, rel_path: &Utf8Path) -> Option<u64> {
+        let metadata = fs::symlink_metadata(self.root.join(rel_path)).ok()?;
+        metadata.is_file().then_some(metadata.len())
+    }
+
+    fn is_executable(&self, rel_path: &Utf8Path) -> bool {
+        fs::metadata(self.root.join(rel_path)).is_ok_and(|m| is_executable(&m))
+    }
+
+    fn mode(&self, rel_path: &Utf8Path) -> Option<u32> {
+        fs::metadata(self.root.join(rel_path)).ok().and_then(|m| mode(&m))
+    }
+
+    fn modified(&self, rel_path: &Utf8Path) -> Option<SystemTime> {
+        fs::metadata(self.root.join(rel_path)).and_then(|m| m.modified()).ok()
+    }
+}
+
+/// case-insensitive, so the walk (and with it which files a hard --max-files cap keeps)
+/// doesn't depend on the filesystem's own directory order
+fn name_order(a: &OsStr, b: &OsStr) -> Ordering {
+    let (a, b) = (a.to_string_lossy(), b.to_string_lossy());
+    a.to_lowercase().cmp(&b.to_lowercase()).then_with(|| a.cmp(&b))
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+// no executable bit to go on, so every shebang counts
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+struct TarFile {
+    data: Vec<u8>,
+    mode: u32,
+    // seconds since the epoch, as the header has it
+    mtime: Option<u64>,
+}
+
+/// a `.tar`, `.tar.gz` or `.tgz` read fully into memory. tar has no index, so there is
+/// no cheaper way to serve reads in arbitrary order
+pub struct TarSource {
+    // sorted by path so children always follow their parent directory
+    entries: Vec<Entry>,
+    files: HashMap<Utf8PathBuf, TarFile>,
+}
+
+impl TarSource {
+    fn open(path: &Utf8Path) -> Result<Self> {
+        let file = fs::File::open(path).with_context(|| format!("failed to open {}", path))?;
+        let lower = path.as_str().to_lowercase();
+        let reader: Box<dyn Read> = if lower.ends_with(".gz") || lower.ends_with(".tgz") {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+
+        let mut archive = tar::Archive::new(reader);
+        let mut dirs = BTreeSet::new();
+        let mut files = HashMap::new();
+        for entry in archive.entries().with_context(|| format!("failed to read {}", path))? {
+            let mut entry = entry?;
+            let entry_path = entry.path()?.into_owned();
+            let Some(rel_path) = Utf8Path::from_path(&entry_path).map(normalize) else {
+                warn!("skipping non-utf8 archive entry: {}", entry_path.display());
+                continue;
+            };
+            if rel_path.as_str().is_empty() {
+                continue;
+            }
+
+            let kind = entry.header().entry_type();
+            if kind.is_dir() {
+                dirs.insert(rel_path);
+            } else if kind.is_file() {
+                let mode = entry.header().mode().unwrap_or(0o644);
+                let mtime = entry.header().mtime().ok();
+                let mut data = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut data)?;
+                files.insert(rel_path, TarFile { data, mode, mtime });
+            } else if kind.is_symlink() || kind.is_hard_link() {
+                warn!("skipping link in archive: {}", rel_path);
+            }
+        }
+
+        // archives don't have to list every directory, so add the implied ones
+        for rel_path in files.keys() {
+            for dir in rel_path.ancestors().skip(1) {
+                if !dir.as_str().is_empty() {
+                    dirs.insert(dir.to_owned());
+                }
+            }
+        }
+
+        let mut entries: Vec<Entry> = dirs
+            .into_iter()
+            .map(|rel_path| Entry { depth: rel_path.components().count(), rel_path, size: None })
+            .chain(files.iter().map(|(rel_path, file)| Entry {
+                rel_path: rel_path.clone(),
+                depth: rel_path.components().count(),
+                size: Some(file.data.len() as u64),
+            }))
+            .collect();
+        entries.sort_by(|a, b| a.rel_path.components().cmp(b.rel_path.components()));
+        debug!("read {} entries from {}", entries.len(), path);
+
+        Ok(TarSource { entries, files })
+    }
+}
+
+fn normalize(path: &Utf8Path) -> Utf8PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, camino::Utf8Component::CurDir | camino::Utf8Component::RootDir))
+        .collect()
+}
+
+impl FileSource for TarSource {
+    fn walk(&self, visit: &mut dyn FnMut(&Entry) -> Result<Visit>) -> Result<()> {
+        let mut skipped: Option<&Utf8Path> = None;
+        for entry in &self.entries {
+            if skipped.is_some_and(|dir| entry.rel_path.starts_with(dir)) {
+                continue;
+            }
+            match visit(entry)? {
+                Visit::Continue => {}
+                Visit::Skip if entry.size.is_none() => skipped = Some(&entry.rel_path),
+                Visit::Skip => {}
+                Visit::Stop => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&self, rel_path: &Utf8Path) -> Result<Vec<u8>> {
+        match self.files.get(rel_path) {
+            Some(file) => Ok(file.data.clone()),
+            None => anyhow::bail!("{} is not in the archive", rel_path),
+        }
+    }
+
+    fn file_size(&self, rel_path: &Utf8Path) -> Option<u64> {
+        self.files.get(rel_path).map(|f| f.data.len() as u64)
+    }
+
+    fn is_executable(&self, rel_path: &Utf8Path) -> bool {
+        self.files.get(rel_path).is_some_and(|f| f.mode & 0o111 != 0)
+    }
+
+    fn mode(&self, rel_path: &Utf8Path) -> Option<u32> {
+        self.files.get(rel_path).map(|f| f.mode & 0o7777)
+    }
+
+    fn modified(&self, rel_path: &Utf8Path) -> Option<SystemTime> {
+        let mtime = self.files.get(rel_path)?.mtime?;
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    /// a gzipped tarball with `(path, mode, contents)` files and no directory entries
+    fn tarball(dir: &TempDir, name: &str, files: &[(&str, u32, &str)]) -> Utf8PathBuf {
+        let path = dir.path().join(name);
+        let mut builder = tar::Builder::new(GzEncoder::new(fs::File::create(&path).unwrap(), Compression::fast()));
+        for &(file_path, mode, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(mode);
+            header.set_mtime(1_700_000_000);
+            header.set_cksum();
+            builder.append_data(&mut header, file_path, contents.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        path
+    }
+
+    fn walk_paths(source: &dyn FileSource) -> Vec<(String, Option<u64>)> {
+        let mut paths = Vec::new();
+        source
+            .walk(&mut |entry| {
+                paths.push((entry.rel_path.to_string(), entry.size));
+                Ok(Visit::Continue)
+            })
+            .unwrap();
+        paths
+    }
+
+    #[test]
+    fn tarball_is_walked_like_a_directory() {
+        let dir = TempDir::new();
+        let path = tarball(
+            &dir,
+            "proj.tar.gz",
+            &[
+                ("./proj/src/main.rs", 0o644, "fn main() {}\n"),
+                ("proj/run.sh", 0o755, "#!/bin/sh\n"),
+                ("proj/README.md", 0o644, "# proj\n"),
+            ],
+        );
+        assert!(is_archive(path.as_str()));
+        let source = open(path.as_str(), true, false).unwrap();
+
+        // the directories the archive only implies are there, each before its children
+        assert_eq!(
+            walk_paths(source.as_ref()),
+            [
+                ("proj".to_string(), None),
+                ("proj/README.md".to_string(), Some(7)),
+                ("proj/run.sh".to_string(), Some(10)),
+                ("proj/src".to_string(), None),
+                ("proj/src/main.rs".to_string(), Some(13)),
+            ]
+        );
+        assert_eq!(source.read("proj/src/main.rs".into()).unwrap(), b"fn main() {}\n");
+        assert!(source.read("proj/missing.rs".into()).is_err());
+        assert_eq!(source.file_size("proj/run.sh".into()), Some(10));
+        assert_eq!(source.file_size("proj/src".into()), None);
+        assert!(source.is_executable("proj/run.sh".into()));
+        assert!(!source.is_executable("proj/README.md".into()));
+        assert_eq!(source.mode("proj/run.sh".into()), Some(0o755));
+        assert_eq!(
+            source.modified("proj/README.md".into()),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+    }
+
+    #[test]
+    fn skipping_a_directory_in_a_tarball_skips_what_is_in_it() {
+        let dir = TempDir::new();
+        let path = tarball(&dir, "proj.tgz", &[("a/x.rs", 0o644, "x"), ("a/b/y.rs", 0o644, "y"), ("c.rs", 0o644, "c")]);
+        let source = open(path.as_str(), false, false).unwrap();
+        let mut visited = Vec::new();
+        source
+            .walk(&mut |entry| {
+                visited.push(entry.rel_path.to_string());
+                Ok(if entry.rel_path == "a" { Visit::Skip } else { Visit::Continue })
+            })
+            .unwrap();
+        assert_eq!(visited, ["a", "c.rs"]);
+    }
+}
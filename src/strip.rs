@@ -0,0 +1,210 @@
+/// how comments and string literals look in one language
+struct Syntax {
+    line: &'static [&'static str],
+    block: Option<(&'static str, &'static str)>,
+    // whether block comments nest, as in rust and haskell
+    nested: bool,
+    // longest first, so `"""` is tried before `"`
+    quotes: &'static [&'static str],
+}
+
+const C_QUOTES: &[&str] = &["\"", "'"];
+const JS_QUOTES: &[&str] = &["\"", "'", "`"];
+const PY_QUOTES: &[&str] = &["\"\"\"", "'''", "\"", "'"];
+
+const C_STYLE: Syntax = Syntax { line: &["//"], block: Some(("/*", "*/")), nested: false, quotes: C_QUOTES };
+const HASH: Syntax = Syntax { line: &["#"], block: None, nested: false, quotes: C_QUOTES };
+
+fn syntax_for(language: &str) -> Option<Syntax> {
+    let syntax = match language {
+        // no single quotes, since they mostly start lifetimes
+        "rust" => Syntax { nested: true, quotes: &["\""], ..C_STYLE },
+        "swift" | "scala" => Syntax { nested: true, ..C_STYLE },
+        "javascript" | "typescript" | "jsx" | "tsx" | "go" => Syntax { quotes: JS_QUOTES, ..C_STYLE },
+        "c" | "cpp" | "csharp" | "java" | "kotlin" | "groovy" | "scss" | "less" => C_STYLE,
+        "php" => Syntax { line: &["//", "#"], ..C_STYLE },
+        "css" => Syntax { line: &[], ..C_STYLE },
+        "python" => Syntax { quotes: PY_QUOTES, ..HASH },
+        "powershell" => Syntax { block: Some(("<#", "#>")), ..HASH },
+        "bash" | "fish" | "ruby" | "perl" | "r" | "elixir" | "yaml" | "toml" | "conf" => HASH,
+        "sql" => Syntax { line: &["--"], ..C_STYLE },
+        "lua" => Syntax { line: &["--"], block: Some(("--[[", "]]")), nested: false, quotes: C_QUOTES },
+        "haskell" | "elm" => Syntax { line: &["--"], block: Some(("{-", "-}")), nested: true, quotes: &["\""] },
+        "erlang" => Syntax { line: &["%"], block: None, nested: false, quotes: &["\""] },
+        // no quotes: apostrophes in text would otherwise hide every comment after them
+        "html" | "xml" => Syntax { line: &[], block: Some(("<!--", "-->")), nested: false, quotes: &[] },
+        _ => return None,
+    };
+    Some(syntax)
+}
+
+enum State {
+    Code,
+    Quoted(&'static str),
+    Comment(usize),
+}
+
+/// removes line and block comments from `content`, or returns `None` for a language it
+/// doesn't know. lines left empty by a removed comment are dropped and runs of blank
+/// lines collapse into one. quotes are tracked so comment markers inside the usual
+/// string literals survive, but this is a heuristic: raw strings, regex literals and
+/// heredocs can still fool it
+pub fn strip_comments(content: &str, language: &str) -> Option<String> {
+    let syntax = syntax_for(language)?;
+    let mut stripped = String::with_capacity(content.len());
+    // for each line of `stripped`, whether a comment was taken out of it
+    let mut commented = vec![false];
+    let mut rest = content;
+    // a shebang looks like a `#` comment but has to stay
+    if syntax.line.contains(&"#") && content.starts_with("#!") {
+        let end = content.find('\n').map_or(content.len(), |i| i + 1);
+        stripped.push_str(&content[..end]);
+        rest = &content[end..];
+        if content[..end].ends_with('\n') {
+            commented.push(false);
+        }
+    }
+
+    let mut state = State::Code;
+    let mut prev: Option<char> = stripped.chars().last();
+    while let Some(c) = rest.chars().next() {
+        let mut advance = c.len_utf8();
+        match state {
+            State::Code => {
+                if let Some((open, _)) = syntax.block.filter(|(open, _)| rest.starts_with(open)) {
+                    state = State::Comment(1);
+                    *commented.last_mut().unwrap() = true;
+                    advance = open.len();
+                } else if let Some(marker) = syntax.line.iter().find(|marker| {
+                    // `$#` and `${#var}` in shell aren't comments
+                    rest.starts_with(*marker) && (**marker != "#" || prev.is_none_or(char::is_whitespace))
+                }) {
+                    *commented.last_mut().unwrap() = true;
+                    advance = rest[marker.len()..].find('\n').map_or(rest.len(), |i| i + marker.len());
+                } else if let Some(quote) = syntax.quotes.iter().find(|quote| rest.starts_with(*quote)) {
+                    state = State::Quoted(quote);
+                    stripped.push_str(quote);
+                    advance = quote.len();
+                } else {
+                    push(&mut stripped, &mut commented, c);
+                }
+            }
+            State::Quoted(quote) => {
+                if c == '\\' {
+                    stripped.push(c);
+                    if let Some(escaped) = rest[1..].chars().next() {
+                        push(&mut stripped, &mut commented, escaped);
+                        advance += escaped.len_utf8();
+                    }
+                } else if rest.starts_with(quote) {
+                    state = State::Code;
+                    stripped.push_str(quote);
+                    advance = quote.len();
+                } else {
+                    // only triple quotes and backticks span lines, so a stray quote can't
+                    // swallow the rest of the file
+                    if c == '\n' && quote.len() == 1 && quote != "`" {
+                        state = State::Code;
+                    }
+                    push(&mut stripped, &mut commented, c);
+                }
+            }
+            State::Comment(depth) => {
+                let (open, close) = syntax.block.unwrap();
+                if rest.starts_with(close) {
+                    state = if depth == 1 { State::Code } else { State::Comment(depth - 1) };
+                    advance = close.len();
+                } else if syntax.nested && rest.starts_with(open) {
+                    state = State::Comment(depth + 1);
+                    advance = open.len();
+                } else if c == '\n' {
+                    push(&mut stripped, &mut commented, c);
+                    *commented.last_mut().unwrap() = true;
+                }
+            }
+        }
+        prev = rest[..advance].chars().last();
+        rest = &rest[advance..];
+    }
+
+    let mut result = String::with_capacity(stripped.len());
+    let mut last_blank = false;
+    for (line, &commented) in stripped.split_inclusive('\n').zip(&commented) {
+        let ending = if line.ends_with("\r\n") {
+            "\r\n"
+        } else if line.ends_with('\n') {
+            "\n"
+        } else {
+            ""
+        };
+        let text = line.trim_end_matches(['\n', '\r']);
+        let text = if commented { text.trim_end() } else { text };
+        if text.trim().is_empty() {
+            if commented || last_blank {
+                continue;
+            }
+            last_blank = true;
+        } else {
+            last_blank = false;
+        }
+        result.push_str(text);
+        result.push_str(ending);
+    }
+    Some(result)
+}
+
+fn push(stripped: &mut String, commented: &mut Vec<bool>, c: char) {
+    stripped.push(c);
+    if c == '\n' {
+        commented.push(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(language: &str, content: &str) -> String {
+        strip_comments(content, language).unwrap()
+    }
+
+    #[test]
+    fn comment_markers_inside_strings_are_kept() {
+        assert_eq!(
+            strip("rust", "let url = \"http://example.com // not a comment\"; // the docs\n"),
+            "let url = \"http://example.com // not a comment\";\n"
+        );
+        assert_eq!(strip("python", "print('# not a comment')  # greet\n"), "print('# not a comment')\n");
+        // an escaped quote doesn't end the string early
+        assert_eq!(strip("c", "puts(\"say \\\"/*\\\"\"); /* gone */\n"), "puts(\"say \\\"/*\\\"\");\n");
+    }
+
+    #[test]
+    fn block_comments_spanning_lines_are_removed_with_their_lines() {
+        assert_eq!(strip("c", "int a;\n/* one\n   two\n*/\nint b;\n"), "int a;\nint b;\n");
+        // rust block comments nest, c ones don't
+        assert_eq!(strip("rust", "/* a /* b */ c */fn f() {}\n"), "fn f() {}\n");
+        assert_eq!(strip("c", "/* a /* b */int c;\n"), "int c;\n");
+    }
+
+    #[test]
+    fn shebang_lines_survive_hash_comments() {
+        assert_eq!(
+            strip("python", "#!/usr/bin/env python3\n# a comment\nprint(1)\n"),
+            "#!/usr/bin/env python3\nprint(1)\n"
+        );
+        // and a `#` that isn't preceded by whitespace isn't a comment in shell
+        assert_eq!(strip("bash", "#!/bin/sh\necho $# args\n"), "#!/bin/sh\necho $# args\n");
+    }
+
+    #[test]
+    fn runs_of_blank_lines_collapse_into_one() {
+        assert_eq!(strip("python", "a = 1\n\n\n\nb = 2\n"), "a = 1\n\nb = 2\n");
+        assert_eq!(strip("rust", "fn a() {}\r\n\r\n// gone\r\n\r\nfn b() {}\r\n"), "fn a() {}\r\n\r\nfn b() {}\r\n");
+    }
+
+    #[test]
+    fn unknown_languages_are_left_to_the_caller() {
+        assert_eq!(strip_comments("# title\n", "markdown"), None);
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::Result;
+use clap::ValueEnum;
+
+use crate::format::fence_for;
+use crate::DumpFile;
+
+const PLACEHOLDERS: &[&str] = &["path", "lang", "content", "size", "ext", "fence"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Preset {
+    /// the built-in markdown blocks, same as no preset
+    Markdown,
+    /// `<file path=... lang=...>` tags
+    Xml,
+    /// a `--- path` line before the bare content
+    Minimal,
+}
+
+impl Preset {
+    /// `None` for markdown, which keeps the built-in renderer and its header notes. the
+    /// others are written just like a `--template`, escapes and all
+    pub fn template(self) -> Option<&'static str> {
+        match self {
+            Preset::Markdown => None,
+            Preset::Xml => Some(r#"<file path="{path}" lang="{lang}" size="{size}">\n{content}\n</file>\n\n"#),
+            Preset::Minimal => Some(r"--- {path}\n{content}\n\n"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Text(String),
+    Placeholder(&'static str),
+}
+
+/// a per-file block layout from `--template`, like `"## {path}\n{content}\n"`. besides
+/// the placeholders, `\n`, `\t` and `\\` are escapes and `{{`/`}}` are literal braces
+#[derive(Debug, Clone)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some('\\') => text.push('\\'),
+                    Some(other) => {
+                        text.push('\\');
+                        text.push(other);
+                    }
+                    None => text.push('\\'),
+                },
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => anyhow::bail!("unclosed {{{} in --template", name),
+                        }
+                    }
+                    let Some(placeholder) = PLACEHOLDERS.iter().find(|p| **p == name) else {
+                        let known: Vec<String> = PLACEHOLDERS.iter().map(|p| format!("{{{}}}", p)).collect();
+                        anyhow::bail!(
+                            "unknown placeholder {{{}}} in --template; use {} ({{{{ and }}}} are literal braces)",
+                            name,
+                            known.join(", ")
+                        );
+                    };
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    pieces.push(Piece::Placeholder(placeholder));
+                }
+                '}' => anyhow::bail!("unmatched }} in --template; write }}}} for a literal brace"),
+                c => text.push(c),
+            }
+        }
+        pieces.push(Piece::Text(text));
+        Ok(Template { pieces })
+    }
+
+    /// one block for `content`, which is the whole file or, with `part`, one chunk of it
+    pub fn render(&self, file: &DumpFile, part: Option<usize>, content: &str) -> String {
+        let mut block = String::with_capacity(content.len() + 100);
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => block.push_str(text),
+                Piece::Placeholder("path") => {
+                    block.push_str(file.path.as_str());
+                    if let Some(n) = part {
+                        block.push_str(&format!(" (part {})", n));
+                    }
+                }
+                Piece::Placeholder("lang") => block.push_str(&file.language),
+                Piece::Placeholder("content") => block.push_str(content),
+                Piece::Placeholder("size") => block.push_str(&file.size_bytes.to_string()),
+                Piece::Placeholder("ext") => block.push_str(file.path.extension().unwrap_or("")),
+                Piece::Placeholder("fence") => block.push_str(&fence_for(content)),
+                Piece::Placeholder(other) => unreachable!("unknown placeholder {}", other),
+            }
+        }
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(preset: Preset) -> String {
+        let file = DumpFile {
+            path: "src/lib.rs".into(),
+            language: "rust".to_string(),
+            size_bytes: 14,
+            mode: None,
+            decoded_from: None,
+            modified: None,
+            author: None,
+            duplicate_of: None,
+            generated: None,
+            content: "pub fn f() {}\n".to_string(),
+        };
+        let template = Template::parse(preset.template().unwrap()).unwrap();
+        template.render(&file, None, &file.content)
+    }
+
+    #[test]
+    fn each_preset_renders_with_real_newlines() {
+        assert_eq!(
+            render(Preset::Xml),
+            "<file path=\"src/lib.rs\" lang=\"rust\" size=\"14\">\npub fn f() {}\n\n</file>\n\n"
+        );
+        assert_eq!(render(Preset::Minimal), "--- src/lib.rs\npub fn f() {}\n\n\n");
+        assert_eq!(Preset::Markdown.template(), None);
+    }
+}
@@ -0,0 +1,37 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// a scratch directory for one test, removed again when it's dropped
+pub struct TempDir {
+    path: Utf8PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("dumpcode-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        let path = Utf8PathBuf::from_path_buf(std::env::temp_dir().join(name)).expect("temp dir is utf-8");
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("failed to create a test directory");
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+
+    /// writes `contents` to `rel_path` below the directory, creating its parents
+    pub fn write(&self, rel_path: &str, contents: impl AsRef<[u8]>) -> Utf8PathBuf {
+        let path = self.path.join(rel_path);
+        fs::create_dir_all(path.parent().unwrap()).expect("failed to create a test directory");
+        fs::write(&path, contents).expect("failed to write a test file");
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,51 @@
+use clap::ValueEnum;
+
+/// rough token estimate without a real tokenizer. bpe tokenizers average around four
+/// characters per token on code, but dense text with many short words runs higher, so
+/// this averages a character-based and a word-based guess. it rounds once, at the end,
+/// so the estimate for pieces of text joined at whitespace is never more than the sum of
+/// theirs, which is what lets --max-tokens budget the dump piece by piece
+pub fn estimate_tokens(text: &str) -> usize {
+    let chars = text.chars().count();
+    let words = text.split_whitespace().count();
+    // (chars / 4 + words * 4 / 3) / 2
+    (chars * 3 + words * 16).div_ceil(24)
+}
+
+/// the character half of `estimate_tokens`, for files that haven't been read yet
+pub fn estimate_tokens_from_size(bytes: u64) -> usize {
+    (bytes / 4) as usize
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Pack {
+    /// smallest files first, to include as many files as possible
+    FitMost,
+    /// largest files first, to prioritize big files
+    FitLargest,
+}
+
+/// decides which files fit in `budget` tokens given each file's cost. without a packing
+/// strategy files are taken in order until the first one that doesn't fit; with one,
+/// every file that still fits is taken in the strategy's order. output order is
+/// unaffected either way
+pub fn select_within_budget(costs: &[usize], budget: usize, pack: Option<Pack>) -> Vec<bool> {
+    let mut order: Vec<usize> = (0..costs.len()).collect();
+    match pack {
+        None => {}
+        Some(Pack::FitMost) => order.sort_by_key(|&i| costs[i]),
+        Some(Pack::FitLargest) => order.sort_by_key(|&i| std::cmp::Reverse(costs[i])),
+    }
+
+    let mut selected = vec![false; costs.len()];
+    let mut used = 0;
+    for i in order {
+        if used + costs[i] <= budget {
+            used += costs[i];
+            selected[i] = true;
+        } else if pack.is_none() {
+            break;
+        }
+    }
+    selected
+}
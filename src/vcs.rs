@@ -0,0 +1,346 @@
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::debug;
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vcs {
+    Git,
+    Mercurial,
+    Subversion,
+}
+
+/// ignore rules from whichever vcs the scan root lives in. paths passed to
+/// `is_ignored` are relative to the scan root, not to the repository
+#[derive(Debug)]
+pub struct VcsIgnore {
+    // where the scan root sits inside the repository, with forward slashes
+    scan_prefix: String,
+    globs: GlobSet,
+    regexes: Vec<Regex>,
+    // svn:ignore is per-directory and not recursive: (directory, basename globs)
+    dir_globs: Vec<(String, GlobSet)>,
+}
+
+impl VcsIgnore {
+    /// returns `None` when the scan root is not under version control
+    pub fn discover(scan_root: &Path) -> Result<Option<Self>> {
+        let scan_root = scan_root
+            .canonicalize()
+            .with_context(|| format!("failed to resolve {}", scan_root.display()))?;
+        let Some((vcs, repo_root)) = detect(&scan_root) else {
+            return Ok(None);
+        };
+        debug!("scan root is inside a {:?} repository at {}", vcs, repo_root.display());
+
+        let scan_prefix = scan_root
+            .strip_prefix(&repo_root)
+            .map(to_slash_path)
+            .unwrap_or_default();
+        let mut ignore = VcsIgnore {
+            scan_prefix,
+            globs: GlobSet::empty(),
+            regexes: Vec::new(),
+            dir_globs: Vec::new(),
+        };
+
+        match vcs {
+            Vcs::Git => {}
+            Vcs::Mercurial => {
+                let hgignore = repo_root.join(".hgignore");
+                if hgignore.is_file() {
+                    let text = fs::read_to_string(&hgignore)
+                        .with_context(|| format!("failed to read {}", hgignore.display()))?;
+                    let (globs, regexes) = parse_hgignore(&text)
+                        .with_context(|| format!("invalid pattern in {}", hgignore.display()))?;
+                    ignore.globs = globs;
+                    ignore.regexes = regexes;
+                }
+            }
+            Vcs::Subversion => ignore.dir_globs = svn_ignores(&repo_root)?,
+        }
+
+        Ok(Some(ignore))
+    }
+
+    pub fn is_ignored(&self, rel_path: &Path) -> bool {
+        let rel = to_slash_path(rel_path);
+        let path = if self.scan_prefix.is_empty() {
+            rel
+        } else {
+            format!("{}/{}", self.scan_prefix, rel)
+        };
+
+        if self.globs.is_match(&path) || self.regexes.iter().any(|re| re.is_match(&path)) {
+            return true;
+        }
+
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", &path));
+        self.dir_globs
+            .iter()
+            .any(|(ignore_dir, globs)| ignore_dir == dir && globs.is_match(name))
+    }
+}
+
+/// files git considers changed below `scan_root`, relative to it: modified or staged
+/// compared with `base`, plus untracked files that aren't ignored
+#[derive(Debug, Clone)]
+pub struct ChangedFiles {
+    files: HashSet<PathBuf>,
+    // every ancestor of a changed file, so the walk can skip everything else
+    dirs: HashSet<PathBuf>,
+}
+
+impl ChangedFiles {
+    pub fn from_git(scan_root: &Path, base: &str) -> Result<Self> {
+        let inside = Command::new("git")
+            .args(["rev-parse", "--is-inside-work-tree"])
+            .current_dir(scan_root)
+            .output()
+            .context("--changed needs git, which could not be run")?;
+        if !inside.status.success() {
+            anyhow::bail!("--changed needs a git repository, but {} is not inside one", scan_root.display());
+        }
+
+        let mut files = HashSet::new();
+        let queries: [&[&str]; 3] = [
+            &["diff", "--name-only", "--relative", base],
+            &["diff", "--name-only", "--relative", "--staged", base],
+            &["ls-files", "--others", "--exclude-standard"],
+        ];
+        for args in queries {
+            let output = Command::new("git").args(args).current_dir(scan_root).output()?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "git {} failed: {}",
+                    args.join(" "),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            files.extend(String::from_utf8_lossy(&output.stdout).lines().map(PathBuf::from));
+        }
+        debug!("{} changed files against {}", files.len(), base);
+
+        let dirs = files
+            .iter()
+            .flat_map(|file| file.ancestors().skip(1))
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .collect();
+        Ok(ChangedFiles { files, dirs })
+    }
+
+    pub fn contains_file(&self, rel_path: &Path) -> bool {
+        self.files.contains(rel_path)
+    }
+
+    /// whether a changed file lives somewhere below this directory
+    pub fn contains_dir(&self, rel_path: &Path) -> bool {
+        self.dirs.contains(rel_path)
+    }
+}
+
+/// the author of the last commit that touched `rel_path`, or `None` outside git or for a
+/// file git doesn't track
+pub fn last_author(scan_root: &Path, rel_path: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .args(["log", "-1", "--format=%an", "--"])
+        .arg(rel_path)
+        .current_dir(scan_root)
+        .output()
+        .ok()?;
+    let author = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !author.is_empty()).then_some(author)
+}
+
+fn detect(scan_root: &Path) -> Option<(Vcs, PathBuf)> {
+    // svn working copies before 1.7 have a .svn in every directory, so keep walking up
+    // to find the topmost one rather than stopping at the first
+    let mut svn_root = None;
+    for dir in scan_root.ancestors() {
+        if dir.join(".git").exists() {
+            return Some((Vcs::Git, dir.to_path_buf()));
+        }
+        if dir.join(".hg").is_dir() {
+            return Some((Vcs::Mercurial, dir.to_path_buf()));
+        }
+        if dir.join(".svn").is_dir() {
+            svn_root = Some(dir.to_path_buf());
+        } else if svn_root.is_some() {
+            break;
+        }
+    }
+
+    svn_root.map(|root| (Vcs::Subversion, root))
+}
+
+/// parses `.hgignore`, honoring `syntax:` switches and per-line `glob:`/`re:` prefixes.
+/// like mercurial, neither syntax is rooted: globs match at any depth and regexes search
+fn parse_hgignore(text: &str) -> Result<(GlobSet, Vec<Regex>)> {
+    let mut globs = GlobSetBuilder::new();
+    let mut regexes = Vec::new();
+    let mut glob_syntax = false;
+
+    for line in text.lines() {
+        let line = strip_hg_comment(line).trim_end();
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(syntax) = line.strip_prefix("syntax:") {
+            glob_syntax = match syntax.trim() {
+                "glob" => true,
+                "regexp" | "re" => false,
+                other => anyhow::bail!("unknown syntax `{}`", other),
+            };
+            continue;
+        }
+
+        let (is_glob, pattern) = if let Some(p) = line.strip_prefix("glob:") {
+            (true, p)
+        } else if let Some(p) = line.strip_prefix("re:").or_else(|| line.strip_prefix("regexp:")) {
+            (false, p)
+        } else {
+            (glob_syntax, line)
+        };
+
+        if is_glob {
+            let pattern = pattern.trim_start_matches("./");
+            globs.add(Glob::new(&format!("**/{}", pattern))?);
+            globs.add(Glob::new(&format!("**/{}/**", pattern))?);
+        } else {
+            regexes.push(Regex::new(pattern)?);
+        }
+    }
+
+    Ok((globs.build()?, regexes))
+}
+
+// `#` starts a comment unless escaped as `\#`
+fn strip_hg_comment(line: &str) -> &str {
+    let bytes = line.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'#' && (i == 0 || bytes[i - 1] != b'\\') {
+            return &line[..i];
+        }
+    }
+    line
+}
+
+/// svn keeps svn:ignore in its working copy database, so ask the client for it.
+/// without an `svn` binary there is nothing to read and no rules apply
+fn svn_ignores(repo_root: &Path) -> Result<Vec<(String, GlobSet)>> {
+    let output = match Command::new("svn")
+        .args(["propget", "svn:ignore", "-R"])
+        .arg(repo_root)
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            debug!("svn propget failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            return Ok(Vec::new());
+        }
+        Err(e) => {
+            debug!("svn client not available, skipping svn:ignore ({})", e);
+            return Ok(Vec::new());
+        }
+    };
+
+    // output looks like `path - pattern` followed by more patterns, one per line,
+    // with a blank line between directories
+    let mut rules = Vec::new();
+    let mut current: Option<(String, GlobSetBuilder)> = None;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let pattern = if let Some((dir, pattern)) = line.split_once(" - ") {
+            if let Some((dir, builder)) = current.take() {
+                rules.push((dir, builder.build()?));
+            }
+            let dir = Path::new(dir);
+            let rel = dir.strip_prefix(repo_root).unwrap_or(dir);
+            current = Some((to_slash_path(rel), GlobSetBuilder::new()));
+            pattern
+        } else {
+            line
+        };
+
+        let pattern = pattern.trim();
+        if let Some((_, builder)) = current.as_mut().filter(|_| !pattern.is_empty()) {
+            builder.add(Glob::new(pattern)?);
+        }
+    }
+    if let Some((dir, builder)) = current {
+        rules.push((dir, builder.build()?));
+    }
+
+    Ok(rules)
+}
+
+fn to_slash_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn hg_repo(hgignore: &str) -> TempDir {
+        let repo = TempDir::new();
+        fs::create_dir(repo.path().join(".hg")).unwrap();
+        repo.write(".hgignore", hgignore);
+        repo
+    }
+
+    #[test]
+    fn hgignore_defaults_to_unrooted_regexps() {
+        let repo = hg_repo("\\.orig$\n^build/\n");
+        let ignore = VcsIgnore::discover(repo.path().as_std_path()).unwrap().unwrap();
+        assert!(ignore.is_ignored(Path::new("main.rs.orig")));
+        assert!(ignore.is_ignored(Path::new("src/deep/main.rs.orig")));
+        assert!(ignore.is_ignored(Path::new("build/out.o")));
+        // a regexp searches the path, so only `^` roots it
+        assert!(!ignore.is_ignored(Path::new("src/build/out.o")));
+        assert!(!ignore.is_ignored(Path::new("main.rs")));
+    }
+
+    #[test]
+    fn hgignore_switches_syntax_and_honours_line_prefixes() {
+        let repo = hg_repo(
+            "syntax: glob\n*.pyc\ntarget\n# a comment\nre:\\.tmp$\n\nsyntax: regexp\n_bak$ # trailing comment\nglob:*.log\n",
+        );
+        let ignore = VcsIgnore::discover(repo.path().as_std_path()).unwrap().unwrap();
+        assert!(ignore.is_ignored(Path::new("app.pyc")));
+        assert!(ignore.is_ignored(Path::new("pkg/app.pyc")));
+        // a glob naming a directory ignores everything in it, at any depth
+        assert!(ignore.is_ignored(Path::new("target/debug/app")));
+        assert!(ignore.is_ignored(Path::new("crates/x/target/debug/app")));
+        assert!(ignore.is_ignored(Path::new("notes.tmp")));
+        assert!(ignore.is_ignored(Path::new("config_bak")));
+        assert!(ignore.is_ignored(Path::new("logs/run.log")));
+        assert!(!ignore.is_ignored(Path::new("src/app.py")));
+        assert!(!ignore.is_ignored(Path::new("targets.txt")));
+    }
+
+    #[test]
+    fn hgignore_rules_are_relative_to_the_repository_not_the_scan_root() {
+        let repo = hg_repo("^src/generated/\n");
+        fs::create_dir(repo.path().join("src")).unwrap();
+        let ignore = VcsIgnore::discover(repo.path().join("src").as_std_path()).unwrap().unwrap();
+        assert!(ignore.is_ignored(Path::new("generated/api.rs")));
+        assert!(!ignore.is_ignored(Path::new("lib.rs")));
+    }
+
+    #[test]
+    fn hgignore_rejects_an_unknown_syntax() {
+        assert!(parse_hgignore("syntax: rootglob\n*.o\n").is_err());
+        // an escaped `#` is part of the pattern
+        let (_, regexes) = parse_hgignore("\\#draft\n").unwrap();
+        assert!(regexes[0].is_match("#draft.txt"));
+    }
+}
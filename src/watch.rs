@@ -0,0 +1,143 @@
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use notify::event::{CreateKind, EventKind};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, SystemTime};
+use walkdir::WalkDir;
+
+/// changes closer together than this trigger one refresh
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+enum Message {
+    Changed(notify::Result<Event>),
+    Interrupted,
+}
+
+/// calls `refresh` whenever something changes below `roots`, until ctrl-c. every
+/// directory is watched on its own rather than recursively, so directories named in
+/// `exclude_dirs` (think `node_modules`) never use up any of the system's watches.
+/// changes to paths `is_ignored` picks out, such as the dump itself, don't count
+pub fn watch(
+    roots: &[String],
+    exclude_dirs: &[String],
+    is_ignored: &dyn Fn(&Path) -> bool,
+    mut refresh: impl FnMut() -> Result<()>,
+) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let interrupt_tx = tx.clone();
+    ctrlc::set_handler(move || {
+        let _ = interrupt_tx.send(Message::Interrupted);
+    })
+    .context("failed to install the ctrl-c handler")?;
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = tx.send(Message::Changed(event));
+    })
+    .context("failed to start watching for changes")?;
+
+    let roots: Vec<PathBuf> =
+        roots.iter().map(|root| Path::new(root).canonicalize()).collect::<Result<_, _>>()?;
+    let is_excluded = |path: &Path| {
+        path.components().any(|c| exclude_dirs.iter().any(|d| c.as_os_str() == d.as_str()))
+    };
+    let mut watched = 0;
+    for root in &roots {
+        watched += watch_tree(&mut watcher, root, exclude_dirs)?;
+    }
+    debug!("watching {} directories", watched);
+    eprintln!("watching for changes, press ctrl-c to stop");
+
+    loop {
+        let event = match rx.recv() {
+            Ok(Message::Changed(event)) => event,
+            Ok(Message::Interrupted) | Err(_) => return Ok(()),
+        };
+        let mut relevant = is_relevant(&event, &roots, is_ignored, &is_excluded);
+        add_new_dirs(&mut watcher, &event, exclude_dirs);
+
+        // wait for the burst to settle, e.g. an editor saving several files
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(Message::Changed(event)) => {
+                    relevant |= is_relevant(&event, &roots, is_ignored, &is_excluded);
+                    add_new_dirs(&mut watcher, &event, exclude_dirs);
+                }
+                Ok(Message::Interrupted) => return Ok(()),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+        if !relevant {
+            continue;
+        }
+
+        // a file vanishing mid-read shouldn't end the session
+        match refresh() {
+            Ok(()) => eprintln!("[{}] dump refreshed", humantime::format_rfc3339_seconds(SystemTime::now())),
+            Err(e) => warn!("refresh failed: {:#}", e),
+        }
+    }
+}
+
+/// watches `dir` and every directory below it that isn't excluded, returning how many
+fn watch_tree(watcher: &mut RecommendedWatcher, dir: &Path, exclude_dirs: &[String]) -> Result<usize> {
+    // an archive root is a single file
+    if dir.is_file() {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        return Ok(1);
+    }
+    let mut count = 0;
+    let walker = WalkDir::new(dir).follow_links(false).into_iter().filter_entry(|entry| {
+        entry.file_type().is_dir()
+            && (entry.depth() == 0 || !exclude_dirs.iter().any(|d| entry.file_name() == d.as_str()))
+    });
+    for entry in walker {
+        let entry = entry?;
+        watcher
+            .watch(entry.path(), RecursiveMode::NonRecursive)
+            .with_context(|| format!("failed to watch {}", entry.path().display()))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn add_new_dirs(watcher: &mut RecommendedWatcher, event: &notify::Result<Event>, exclude_dirs: &[String]) {
+    let Ok(event) = event else {
+        return;
+    };
+    if !matches!(event.kind, EventKind::Create(CreateKind::Folder | CreateKind::Any)) {
+        return;
+    }
+    for path in event.paths.iter().filter(|path| path.is_dir()) {
+        let excluded = path.file_name().is_some_and(|name| exclude_dirs.iter().any(|d| name == d.as_str()));
+        if !excluded {
+            if let Err(e) = watch_tree(watcher, path, exclude_dirs) {
+                warn!("not watching new directory {}: {:#}", path.display(), e);
+            }
+        }
+    }
+}
+
+fn is_relevant(
+    event: &notify::Result<Event>,
+    roots: &[PathBuf],
+    is_ignored: &dyn Fn(&Path) -> bool,
+    is_excluded: &dyn Fn(&Path) -> bool,
+) -> bool {
+    let event = match event {
+        Ok(event) => event,
+        Err(e) => {
+            warn!("watch error: {}", e);
+            return false;
+        }
+    };
+    if matches!(event.kind, EventKind::Access(_)) {
+        return false;
+    }
+    event.paths.iter().any(|path| {
+        // only the part below the root can be excluded, not wherever the root itself lives
+        let below_root = roots.iter().find_map(|root| path.strip_prefix(root).ok()).unwrap_or(path);
+        !is_ignored(path) && !is_excluded(below_root)
+    })
+}